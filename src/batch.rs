@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::output::{self, Record};
+use crate::repl::Session;
+use crate::settings::OutputFormat;
+use crate::{evaluate_input, error_pointer};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Summary {
+    pub evaluated: usize,
+    pub failed: usize,
+}
+
+// Evaluates every file in turn, "-" reads stdin. Returns whether all lines succeeded
+pub(crate) fn run_batch(files: &[String], session: &mut Session, keep_going: bool) -> bool {
+    let mut summary = Summary::default();
+    let mut stdout = io::stdout().lock();
+    let mut stderr = io::stderr().lock();
+
+    if let Some(header) = output::header(session.settings.format) {
+        println!("{header}");
+    }
+
+    for file in files {
+        let result = if file == "-" {
+            evaluate_lines(io::stdin().lock(), "<stdin>", session, keep_going, &mut stdout, &mut stderr, &mut summary)
+        }
+        else {
+            match File::open(file) {
+                Ok(opened) => evaluate_lines(BufReader::new(opened), file, session, keep_going, &mut stdout, &mut stderr, &mut summary),
+                Err(error) => {
+                    eprintln!("ERROR: Could not open {file}: {error}");
+                    return false;
+                },
+            }
+        };
+
+        match result {
+            Ok(true) => {},
+            Ok(false) => return false,
+            Err(error) => {
+                eprintln!("ERROR: Could not read {file}: {error}");
+                return false;
+            },
+        }
+    }
+
+    if keep_going {
+        eprintln!("{} evaluated, {} failed", summary.evaluated, summary.failed);
+    }
+
+    summary.failed == 0
+}
+
+// Runs the prelude of the config file into the session, printing only errors.
+// Returns false at the first line that fails
+pub(crate) fn run_prelude(file: &Path, session: &mut Session) -> bool {
+    let source = file.display().to_string();
+    let result = File::open(file).and_then(|opened| {
+        evaluate_lines(BufReader::new(opened), &source, session, false, &mut io::sink(), &mut io::stderr().lock(), &mut Summary::default())
+    });
+    match result {
+        Ok(succeeded) => succeeded,
+        Err(error) => {
+            eprintln!("ERROR: Could not read prelude {source}: {error}");
+            false
+        },
+    }
+}
+
+// Lines such as w = 3 define values and :set NAME VALUE changes a setting for the
+// lines after them. Returns false when an error should stop the batch
+pub(crate) fn evaluate_lines(
+    reader: impl BufRead,
+    source: &str,
+    session: &mut Session,
+    keep_going: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    summary: &mut Summary,
+) -> io::Result<bool> {
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        // Blank lines and # comments are skipped
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        summary.evaluated += 1;
+        if let Some((formatted, result)) = session.apply_script_line(trimmed) {
+            if let Err(error) = result {
+                write!(err, "{source}:{}: ERROR: {error}\n{}", index + 1, error_pointer(&formatted, &error))?;
+                summary.failed += 1;
+                if !keep_going {
+                    return Ok(false);
+                }
+            }
+            continue;
+        }
+
+        let settings = &session.settings;
+        let (formatted, result) = evaluate_input(trimmed, settings);
+        match (&result, settings.format) {
+            (Ok(value), OutputFormat::Plain) => writeln!(out, "{}", value.display(&settings.number))?,
+            (Err(error), OutputFormat::Plain) => {
+                write!(err, "{source}:{}: ERROR: {error}\n{}", index + 1, error_pointer(&formatted, error))?;
+            },
+            // Structured formats report errors in the record itself
+            (_, format) => {
+                let record = Record { input: trimmed, expression: &formatted, result: &result, number: &settings.number };
+                writeln!(out, "{}", output::render(format, &record))?;
+            },
+        }
+
+        if result.is_err() {
+            summary.failed += 1;
+            if !keep_going {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,281 @@
+use std::io::{self, IsTerminal};
+
+use crate::error::CalcError;
+use crate::locale::Locale;
+use crate::settings::{AngleUnit, ImplicitMul, MAX_DIGITS, Notation, OutputFormat, Precision, Settings, Style, Syntax};
+use crate::sheet;
+
+pub(crate) const USAGE: &str = "\
+Usage: calculator [OPTIONS] [EXPRESSION...]
+       calculator fmt [OPTIONS] [EXPRESSION...]
+       calculator convert --to <SYNTAX> [OPTIONS] [EXPRESSION...]
+       calculator sheet --csv <FILE> [OPTIONS]
+
+Evaluates EXPRESSION and prints only the result. Without an expression,
+evaluates one expression per line from the given files or piped stdin,
+skipping blank lines and lines starting with #. Otherwise prompts for
+one interactively. Use -- before an expression that would look like an
+option, such as -- -pi / 2.
+
+In files, name = expression defines a value for the lines after it and
+:set NAME VALUE changes a setting, so a session saved with :save replays
+with -f.
+
+fmt rewrites expressions in canonical form with only the parentheses
+they need, the same way each time. Files are rewritten line by line to
+stdout, keeping blank lines and comments.
+
+convert reads expressions in the --syntax given and writes them in the
+--to syntax, line by line like fmt.
+
+Defaults for the options come from calculator/config.toml in
+XDG_CONFIG_HOME or ~/.config, or the file CALCULATOR_CONFIG names (empty
+for none). It sets keys such as
+
+    digits = 4
+    notation = \"eng\"
+    locale = \"de\"
+    prelude = \"constants.calc\"
+
+with the names and values of :set. prelude names a file of definitions,
+read like -f and relative to the config file, that expressions, files
+and the interactive session start with. backend takes only \"f64\", the
+one numeric backend there is.
+
+sheet evaluates the cells of a CSV file that start with =, such as
+=B2*C2, after the cells they refer to, and prints the file as CSV with
+each formula replaced by its value. Empty cells and cells beyond the
+file count as 0, and IF takes any number but 0 as true. A range covers
+at most 100000 cells.
+
+Options:
+  -f, --file <FILE>            Evaluate each line of FILE, - for stdin
+  -k, --keep-going             Report failing lines and continue, then summarize
+  --explain                    Show each operation carried out, in order, on
+                               the way to the result
+  --latex                      Print the expression and result as LaTeX math
+  --rpn                        Reverse Polish Notation mode, 3 4 + pushes 3 and
+                               4 and adds them, the stack is shown after each line
+  --format <plain|json|csv|tsv>
+                               Print each input, normalized expression,
+                               result, result type, error and warnings
+  --digits <N>                 Round results to N digits after the point
+  --sig <N>                    Round results to N significant figures
+  --sci                        Scientific notation, 1.2345e3
+  --eng                        Engineering notation, 12.345e3
+  --locale <c|en|ch|de|fr>     Decimal and digit group separators for input
+                               and results. With a decimal comma (de, fr)
+                               function arguments are separated by ';'
+  --implicit <standard|tight>  Precedence of implicit multiplication
+  --angle <rad|deg>            Unit of the angles trigonometric functions take
+                               and give
+  --syntax <SYNTAX>            Read expressions as infix (the default), latex
+                               math such as \\frac{1}{2} + \\sqrt{3} \\cdot \\pi,
+                               rpn such as 3 4 + 2 *, sexpr such as
+                               (* (+ 3 4) 2) or sheet such as =SUM(A1:A3)*2
+  --grid <FILE>                Cells of a CSV file, A1 for the first field of
+                               the first row, for --syntax sheet
+  -h, --help                   Show this message
+
+Format options:
+  --compact                    No spaces around operators, 2+3*4
+  --times                      Write multiplication as ×
+  --check                      Print nothing, report lines that would change
+                               and fail if there are any
+
+Convert options:
+  --to <infix|latex|rpn|sexpr> Syntax to write expressions in
+
+Sheet options:
+  --csv <FILE>                 CSV file to evaluate, - for stdin
+";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Command {
+    #[default]
+    Evaluate,
+    // Rewrite expressions in canonical form
+    Format,
+    // Rewrite expressions in another syntax
+    Convert(Syntax),
+    // Evaluate the formulas of a CSV file
+    Sheet,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Args {
+    pub command: Command,
+    pub settings: Settings,
+    // Positional arguments joined into a single expression
+    pub expression: Option<String>,
+    pub files: Vec<String>,
+    // CSV file of the sheet subcommand
+    pub csv: Option<String>,
+    pub keep_going: bool,
+    // Print every evaluation step instead of only the result
+    pub explain: bool,
+    // Print the expression and result as LaTeX
+    pub latex: bool,
+    // Stack based input read line by line
+    pub rpn: bool,
+    // Output style and check only mode of fmt
+    pub style: Style,
+    pub check: bool,
+    pub help: bool,
+}
+
+// Options override the settings given, which come from the config file
+pub(crate) fn parse_args(args: impl Iterator<Item = String>, settings: Settings) -> Result<Args, CalcError> {
+    let mut parsed = Args { settings, ..Args::default() };
+    let mut positional = Vec::<String>::new();
+    let mut options_done = false;
+    let mut args = args.peekable();
+    // First option that only makes sense for a subcommand
+    let mut command_option = None::<String>;
+
+    // Target syntax of convert, which has no default
+    let mut convert_to = None::<Syntax>;
+    let mut grid = false;
+    let subcommand = args.next_if(|arg| arg == "fmt" || arg == "convert" || arg == "sheet");
+    match subcommand.as_deref() {
+        Some("fmt") => parsed.command = Command::Format,
+        Some("convert") => parsed.command = Command::Convert(Syntax::Infix),
+        Some(_) => parsed.command = Command::Sheet,
+        None => {},
+    }
+
+    while let Some(arg) = args.next() {
+        // Anything that is not an option is part of the expression
+        if options_done || !is_option(&arg) {
+            positional.push(arg);
+            continue;
+        }
+
+        match arg.as_str() {
+            "--" => options_done = true,
+            "-h" | "--help" => parsed.help = true,
+            "-f" | "--file" => parsed.files.push(option_value(&mut args, &arg)?),
+            "--csv" => parsed.csv = Some(option_value(&mut args, &arg)?),
+            "-k" | "--keep-going" => parsed.keep_going = true,
+            "--explain" => parsed.explain = true,
+            "--latex" => parsed.latex = true,
+            "--rpn" => parsed.rpn = true,
+            "--compact" | "--times" | "--check" => {
+                match arg.as_str() {
+                    "--compact" => parsed.style.spaces = false,
+                    "--times" => parsed.style.times = '×',
+                    _ => parsed.check = true,
+                }
+                command_option.get_or_insert(arg);
+            },
+            "--to" => {
+                let syntax = option_value(&mut args, &arg)?;
+                convert_to = Some(Syntax::from_name(&syntax)?);
+            },
+            "--format" => {
+                let format = option_value(&mut args, &arg)?;
+                parsed.settings.format = OutputFormat::from_name(&format)?;
+            },
+            "--digits" => {
+                let digits = option_value(&mut args, &arg)?;
+                parsed.settings.number.precision = Precision::decimals(parse_count(&arg, &digits)?)?;
+            },
+            "--sig" => {
+                let figures = option_value(&mut args, &arg)?;
+                parsed.settings.number.precision = Precision::significant(parse_count(&arg, &figures)?)?;
+            },
+            "--sci" => parsed.settings.number.notation = Notation::Scientific,
+            "--eng" => parsed.settings.number.notation = Notation::Engineering,
+            "--locale" => {
+                let locale = option_value(&mut args, &arg)?;
+                parsed.settings.number.locale = Locale::from_name(&locale)?;
+            },
+            "--syntax" => {
+                let syntax = option_value(&mut args, &arg)?;
+                parsed.settings.syntax = Syntax::from_name(&syntax)?;
+            },
+            "--grid" => {
+                let path = option_value(&mut args, &arg)?;
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|error| CalcError::new(format!("Could not read {path}: {error}")))?;
+                parsed.settings.scope = sheet::read_grid(&text)?;
+                grid = true;
+            },
+            "--implicit" => {
+                let mode = option_value(&mut args, &arg)?;
+                parsed.settings.implicit = ImplicitMul::from_name(&mode)?;
+            },
+            "--angle" => {
+                let unit = option_value(&mut args, &arg)?;
+                parsed.settings.angle = AngleUnit::from_name(&unit)?;
+            },
+            _ => return Err(CalcError::new(format!("Unknown option: {arg}"))),
+        }
+    }
+
+    if !positional.is_empty() {
+        if !parsed.files.is_empty() {
+            return Err(CalcError::new("An expression cannot be combined with --file"));
+        }
+        parsed.expression = Some(positional.join(" "));
+    }
+    if let Some(option) = command_option && parsed.command != Command::Format {
+        return Err(CalcError::new(format!("{option} only applies to fmt")));
+    }
+    match (parsed.command, convert_to) {
+        (Command::Convert(_), Some(Syntax::Sheet)) => return Err(CalcError::new("convert reads sheet formulas but cannot write them")),
+        (Command::Convert(_), Some(to)) => parsed.command = Command::Convert(to),
+        (Command::Convert(_), None) => return Err(CalcError::new("convert requires --to <infix|latex|rpn|sexpr>")),
+        (_, Some(_)) => return Err(CalcError::new("--to only applies to convert")),
+        (_, None) => {},
+    }
+    match (parsed.command, &parsed.csv) {
+        (Command::Sheet, None) => return Err(CalcError::new("sheet requires --csv <FILE>")),
+        (Command::Sheet, Some(_)) => {},
+        (_, Some(_)) => return Err(CalcError::new("--csv only applies to sheet")),
+        (_, None) => {},
+    }
+    if parsed.command == Command::Sheet && (parsed.expression.is_some() || !parsed.files.is_empty() || parsed.explain || parsed.latex || parsed.settings.format != OutputFormat::Plain) {
+        return Err(CalcError::new("sheet reads its cells from --csv and prints them as CSV"));
+    }
+    if matches!(parsed.command, Command::Format | Command::Convert(_)) && (parsed.explain || parsed.latex || parsed.settings.format != OutputFormat::Plain) {
+        return Err(CalcError::new("fmt and convert print expressions, not results"));
+    }
+    if parsed.rpn && (parsed.command != Command::Evaluate || parsed.expression.is_some() || !parsed.files.is_empty() || parsed.explain || parsed.latex) {
+        return Err(CalcError::new("--rpn reads its input line by line and takes no expression, files or other modes"));
+    }
+    if grid && parsed.settings.syntax != Syntax::Sheet {
+        return Err(CalcError::new("--grid only applies to --syntax sheet"));
+    }
+    if parsed.explain && parsed.latex {
+        return Err(CalcError::new("--explain cannot be combined with --latex"));
+    }
+    // Without an expression, piped stdin would be evaluated line by line as with --file
+    let batch = !parsed.files.is_empty() || (parsed.expression.is_none() && !io::stdin().is_terminal());
+    for (option, set) in [("--explain", parsed.explain), ("--latex", parsed.latex)] {
+        if set && (batch || parsed.settings.format != OutputFormat::Plain) {
+            return Err(CalcError::new(format!("{option} works on a single expression with plain output")));
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn is_option(arg: &str) -> bool {
+    // Negative numbers such as -3 are expressions rather than options
+    arg.starts_with('-') && arg.chars().nth(1).is_some_and(|c| c == '-' || c.is_ascii_alphabetic())
+}
+
+fn parse_count(option: &str, value: &str) -> Result<usize, CalcError> {
+    let count = value.parse::<usize>()
+        .map_err(|_| CalcError::new(format!("{option} requires a whole number, found {value}")))?;
+    if count > MAX_DIGITS {
+        return Err(CalcError::new(format!("{option} must be at most {MAX_DIGITS}, found {value}")));
+    }
+    Ok(count)
+}
+
+fn option_value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, CalcError> {
+    args.next()
+        .ok_or_else(|| CalcError::new(format!("{option} requires a value")))
+}
@@ -0,0 +1,62 @@
+use crate::eval::{Scope, Value};
+use crate::functions::{CONSTANTS, FUNCTIONS};
+use crate::repl::COMMANDS;
+use crate::settings::NumberFormat;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Candidate {
+    // Replacement for the word being completed
+    pub text: String,
+    // Shown when several candidates fit, such as atan2(y, x)
+    pub display: String,
+}
+
+// Start of the word before the cursor, in characters, and what it may complete to
+pub(crate) fn complete(line: &str, cursor: usize, scope: &Scope, number: &NumberFormat) -> (usize, Vec<Candidate>) {
+    let chars: Vec<char> = line.chars().collect();
+    let mut start = cursor.min(chars.len());
+    while start > 0 && (chars[start - 1].is_alphanumeric() || chars[start - 1] == '_') {
+        start -= 1;
+    }
+    let word: String = chars[start..cursor].iter().collect();
+
+    // Commands only start a line
+    if start == 1 && chars[0] == ':' {
+        let candidates = COMMANDS.iter()
+            .filter(|(command, ..)| command[1..].starts_with(&word))
+            .map(|(command, usage, _)| Candidate { text: command[1..].to_string(), display: format!("{command} {usage}").trim_end().to_string() })
+            .collect();
+        return (start, candidates);
+    }
+    if word.is_empty() || chars[start].is_ascii_digit() {
+        return (start, Vec::new());
+    }
+
+    let mut candidates = Vec::<Candidate>::new();
+    for function in FUNCTIONS.iter().filter(|function| function.name.starts_with(&word)) {
+        candidates.push(Candidate { text: format!("{}(", function.name), display: function.signature() });
+    }
+    for (name, value) in CONSTANTS.iter().filter(|(name, _)| name.starts_with(&word)) {
+        candidates.push(Candidate { text: name.to_string(), display: format!("{name} = {}", Value::Number(*value).display(number)) });
+    }
+    let mut variables: Vec<(&String, _)> = scope.iter().filter(|(name, _)| name.starts_with(&word)).collect();
+    variables.sort_by_key(|(name, _)| *name);
+    for (name, value) in variables {
+        candidates.push(Candidate { text: name.clone(), display: format!("{name} = {}", value.display(number)) });
+    }
+
+    (start, candidates)
+}
+
+// Longest text every candidate starts with
+pub(crate) fn common_prefix(candidates: &[Candidate]) -> String {
+    let Some((first, rest)) = candidates.split_first() else {
+        return String::new();
+    };
+    let mut prefix: Vec<char> = first.text.chars().collect();
+    for candidate in rest {
+        let len = prefix.iter().zip(candidate.text.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(len);
+    }
+    prefix.into_iter().collect()
+}
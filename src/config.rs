@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::CalcError;
+use crate::settings::{SETTING_NAMES, Settings};
+
+// Keys of the config file besides the settings, with the values they take
+const OTHER_KEYS: &[(&str, &str)] = &[
+    ("backend", "f64"),
+    ("prelude", "script of definitions run at startup"),
+];
+
+// Numeric backends a config may ask for. Only f64 exists, the key is there so
+// that a config written for another one fails loudly instead of computing in f64
+const BACKENDS: &[&str] = &["f64"];
+
+// Defaults read from config.toml before the command line, which overrides them
+#[derive(Debug, Default)]
+pub(crate) struct Config {
+    pub settings: Settings,
+    // Script run before anything else, as a file given to -f
+    pub prelude: Option<PathBuf>,
+}
+
+// CALCULATOR_CONFIG names the file, empty for none. Otherwise it is
+// calculator/config.toml in XDG_CONFIG_HOME or ~/.config
+pub(crate) fn config_file() -> Option<PathBuf> {
+    if let Some(file) = std::env::var_os("CALCULATOR_CONFIG") {
+        return (!file.is_empty()).then(|| PathBuf::from(file));
+    }
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("calculator").join("config.toml"))
+}
+
+// Defaults when there is no config file
+pub(crate) fn load() -> Result<Config, CalcError> {
+    let Some(file) = config_file() else {
+        return Ok(Config::default());
+    };
+    let text = match std::fs::read_to_string(&file) {
+        Ok(text) => text,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(error) => return Err(CalcError::new(format!("Could not read {}: {error}", file.display()))),
+    };
+    let dir = file.parent().unwrap_or(Path::new(""));
+    parse_config(&text, dir).map_err(|(line, error)| CalcError::new(format!("{}:{line}: {error}", file.display())))
+}
+
+// Top level keys of TOML with string, integer and boolean values. A relative
+// prelude is found in dir. Errors come with their line number
+pub(crate) fn parse_config(text: &str, dir: &Path) -> Result<Config, (usize, CalcError)> {
+    let mut config = Config::default();
+    let mut seen = Vec::<String>::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let failed = |message: String| (index + 1, CalcError::new(message));
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(failed(format!("Tables are not supported: {line}")));
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(failed(format!("Expected key = value, found {line}")));
+        };
+        let key = key.trim().trim_matches('"');
+        let value = parse_value(value.trim()).map_err(|error| (index + 1, error))?;
+        if seen.iter().any(|done| done == key) {
+            return Err(failed(format!("Duplicate key: {key}")));
+        }
+        seen.push(key.to_string());
+
+        match key {
+            "prelude" => config.prelude = Some(dir.join(value)),
+            "backend" if !BACKENDS.contains(&value.as_str()) => {
+                return Err(failed(format!("Unsupported backend: {value} (only {} is available)", BACKENDS.join(", "))));
+            },
+            "backend" => {},
+            _ if SETTING_NAMES.iter().any(|(name, _)| *name == key) => {
+                config.settings.set(key, &value).map_err(|error| (index + 1, error))?;
+            },
+            _ => {
+                let keys: Vec<&str> = SETTING_NAMES.iter().chain(OTHER_KEYS).map(|(name, _)| *name).collect();
+                return Err(failed(format!("Unknown key: {key} (expected {})", keys.join(", "))));
+            },
+        }
+    }
+
+    Ok(config)
+}
+
+// Everything before a # that is not inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None::<char>;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            },
+            (Some(open), _) if c == open && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {},
+        }
+        escaped = false;
+    }
+    line
+}
+
+// Value as the text settings take, so 3 and "3" are the same
+fn parse_value(value: &str) -> Result<String, CalcError> {
+    if let Some(literal) = value.strip_prefix('\'') {
+        return literal.strip_suffix('\'')
+            .map(String::from)
+            .ok_or_else(|| CalcError::new(format!("Unterminated string: {value}")));
+    }
+    if let Some(basic) = value.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = basic.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if chars.as_str().is_empty() => return Ok(text),
+                '"' => return Err(CalcError::new(format!("Unexpected text after the string {value}"))),
+                '\\' => match chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(escaped @ ('"' | '\\')) => text.push(escaped),
+                    _ => return Err(CalcError::new(format!("Unknown escape in {value}"))),
+                },
+                _ => text.push(c),
+            }
+        }
+        return Err(CalcError::new(format!("Unterminated string: {value}")));
+    }
+    if value == "true" || value == "false" || value.parse::<i64>().is_ok() {
+        return Ok(value.to_string());
+    }
+    Err(CalcError::new(format!("Expected a string, number or boolean, found {value}")))
+}
@@ -0,0 +1,205 @@
+use crate::error::CalcError;
+use crate::functions;
+use crate::latex;
+use crate::notation;
+use crate::parse_input;
+use crate::parse_tokens;
+use crate::parser::Expr;
+use crate::printer;
+use crate::settings::{NumberFormat, Settings, Syntax};
+use crate::token::TokenType;
+
+// Reads input in the syntax of the settings and writes it out in another one
+pub(crate) fn convert(input: &str, settings: &Settings, to: Syntax) -> (String, Result<String, CalcError>) {
+    let (formatted, expr) = parse_input(input, settings);
+    (formatted, expr.map(|expr| render(&expr, to)))
+}
+
+pub(crate) fn render(expr: &Expr, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Infix => printer::print(expr),
+        Syntax::Latex => latex::to_latex(expr),
+        Syntax::Rpn => to_rpn(expr),
+        Syntax::Sexpr => to_sexpr(expr),
+        // Rejected as a target on the command line, cells read back as names
+        Syntax::Sheet => printer::print(expr),
+    }
+}
+
+// Operands first, then what is done with them, 3 4 + 2 *
+pub(crate) fn to_rpn(expr: &Expr) -> String {
+    match expr {
+        Expr::Negate(operand) => format!("{} neg", to_rpn(operand)),
+        Expr::Binary(ttype, lhs, rhs) => format!("{} {} {}", to_rpn(lhs), to_rpn(rhs), printer::symbol(*ttype)),
+        Expr::Call(name, args) => {
+            let words: Vec<String> = args.iter().map(to_rpn).collect();
+            // Functions of any number of values are told how many to take
+            match functions::find_function(name) {
+                Some(function) if function.variadic() && args.len() != function.arity() => format!("{} {name}:{}", words.join(" "), args.len()),
+                _ => format!("{} {name}", words.join(" ")),
+            }
+        },
+        Expr::Conditional(condition, then, otherwise) => format!("{} {} {} ?", to_rpn(condition), to_rpn(then), to_rpn(otherwise)),
+        _ => atom(expr),
+    }
+}
+
+// Operator first inside parentheses, (* (+ 3 4) 2)
+pub(crate) fn to_sexpr(expr: &Expr) -> String {
+    match expr {
+        Expr::Negate(operand) => format!("(- {})", to_sexpr(operand)),
+        Expr::Binary(ttype, lhs, rhs) => format!("({} {} {})", printer::symbol(*ttype), to_sexpr(lhs), to_sexpr(rhs)),
+        Expr::Call(name, args) => {
+            let args: Vec<String> = args.iter().map(to_sexpr).collect();
+            format!("({name} {})", args.join(" "))
+        },
+        Expr::Conditional(condition, then, otherwise) => format!("(if {} {} {})", to_sexpr(condition), to_sexpr(then), to_sexpr(otherwise)),
+        _ => atom(expr),
+    }
+}
+
+fn atom(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(number) => notation::format_number(*number, &NumberFormat::default()),
+        Expr::Text(text) => format!("\"{text}\""),
+        Expr::Bool(value) => value.to_string(),
+        Expr::Name(name) => name.clone(),
+        _ => printer::print(expr),
+    }
+}
+
+// Expression tree of an S-expression such as (* (+ 3 4) 2)
+pub(crate) fn parse_sexpr(input: &str) -> Result<Expr, CalcError> {
+    let parts = sexpr_parts(input)?;
+    let mut pos = 0;
+    let expr = sexpr(&parts, &mut pos)?;
+
+    match parts.get(pos) {
+        Some(part) => Err(CalcError::new(format!("Unexpected token after the expression: {part}"))),
+        None => Ok(expr),
+    }
+}
+
+// Parentheses, quoted text and runs of anything else
+fn sexpr_parts(input: &str) -> Result<Vec<String>, CalcError> {
+    let mut parts = Vec::<String>::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {},
+            '(' | ')' => parts.push(c.to_string()),
+            '"' => {
+                let mut text = String::from('"');
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(CalcError::new("Unterminated text, expected a closing '\"'")),
+                    }
+                }
+                text.push('"');
+                parts.push(text);
+            },
+            _ => {
+                let mut word = c.to_string();
+                while let Some(next) = chars.next_if(|next| !next.is_whitespace() && !matches!(next, '(' | ')' | '"')) {
+                    word.push(next);
+                }
+                parts.push(word);
+            },
+        }
+    }
+
+    Ok(parts)
+}
+
+fn sexpr(parts: &[String], pos: &mut usize) -> Result<Expr, CalcError> {
+    let part = parts.get(*pos).ok_or_else(|| CalcError::new("Unexpected end of expression"))?;
+    *pos += 1;
+
+    match part.as_str() {
+        "(" => {},
+        ")" => return Err(CalcError::new("Unmatched ')'")),
+        _ => return sexpr_atom(part),
+    }
+
+    let head = parts.get(*pos).ok_or_else(|| CalcError::new("'(' is never closed, expected ')'"))?;
+    *pos += 1;
+    let mut args = Vec::<Expr>::new();
+    loop {
+        match parts.get(*pos).map(String::as_str) {
+            Some(")") => {
+                *pos += 1;
+                break;
+            },
+            Some(_) => args.push(sexpr(parts, pos)?),
+            None => return Err(CalcError::new("'(' is never closed, expected ')'")),
+        }
+    }
+
+    sexpr_list(head, args)
+}
+
+fn sexpr_list(head: &str, mut args: Vec<Expr>) -> Result<Expr, CalcError> {
+    if head == "if" {
+        let [condition, then, otherwise] = <[Expr; 3]>::try_from(args)
+            .map_err(|_| CalcError::new("(if condition then else) takes 3 arguments"))?;
+        return Ok(Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)));
+    }
+    if head == "-" && args.len() == 1 {
+        return Ok(Expr::Negate(Box::new(args.remove(0))));
+    }
+
+    if let Some(function) = functions::find_function(head) {
+        if !function.accepts(args.len()) {
+            return Err(CalcError::new(format!("{} takes {} argument(s) but {} were given", function.signature(), function.takes(), args.len())));
+        }
+        return Ok(Expr::Call(function.name.to_string(), args));
+    }
+
+    let ttype = parse_tokens(head).ok()
+        .and_then(|tokens| tokens.first().map(|token| token.ttype))
+        .filter(|ttype| is_operator(*ttype))
+        .ok_or_else(|| CalcError::new(format!("Unknown function: {head}")))?;
+
+    // Sums, products and joined text take any number of operands, (+ 1 2 3) is 1 + 2 + 3
+    let variadic = matches!(ttype, TokenType::Addition | TokenType::Multiply | TokenType::Concat);
+    if args.len() < 2 || (!variadic && args.len() > 2) {
+        let expected = if variadic { "2 or more" } else { "2" };
+        return Err(CalcError::new(format!("({head} ...) takes {expected} arguments but {} were given", args.len())));
+    }
+    let first = args.remove(0);
+    Ok(args.into_iter().fold(first, |lhs, rhs| Expr::Binary(ttype, Box::new(lhs), Box::new(rhs))))
+}
+
+fn sexpr_atom(part: &str) -> Result<Expr, CalcError> {
+    if part.starts_with('"') {
+        return Ok(Expr::Text(part[1..part.len() - 1].to_string()));
+    }
+    if part.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') && let Ok(number) = part.parse::<f64>() {
+        return Ok(Expr::Number(number));
+    }
+    if part.starts_with(char::is_alphabetic) {
+        return Ok(Expr::Name(part.to_string()));
+    }
+
+    Err(CalcError::new(format!("Unexpected token: {part}")))
+}
+
+fn is_operator(ttype: TokenType) -> bool {
+    matches!(ttype,
+        TokenType::Addition |
+        TokenType::Subtract |
+        TokenType::Multiply |
+        TokenType::Division |
+        TokenType::Exponent |
+        TokenType::Equal |
+        TokenType::NotEqual |
+        TokenType::Less |
+        TokenType::LessEqual |
+        TokenType::Greater |
+        TokenType::GreaterEqual |
+        TokenType::Concat
+    )
+}
@@ -0,0 +1,488 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+
+use crate::completion::{self, Candidate};
+use crate::highlight;
+use crate::terminal::RawMode;
+
+// Lines kept in the history file
+const HISTORY_LIMIT: usize = 1000;
+
+// Finds the start of the word before the cursor and what it may complete to
+pub(crate) type Completer<'a> = &'a dyn Fn(&str, usize) -> (usize, Vec<Candidate>);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    WordLeft,
+    WordRight,
+    Home,
+    End,
+    Up,
+    Down,
+    Tab,
+    // Ctrl-R, search the history
+    Search,
+    // Ctrl-C or Ctrl-G
+    Cancel,
+    // Ctrl-D
+    Eof,
+    // Ctrl-K, Ctrl-U and Ctrl-W
+    KillEnd,
+    KillStart,
+    KillWord,
+    // Ctrl-L
+    ClearScreen,
+    Ignored,
+}
+
+// Reads one key press, None at the end of input
+fn read_key(input: &mut impl Read) -> io::Result<Option<Key>> {
+    let Some(byte) = read_byte(input)? else {
+        return Ok(None);
+    };
+
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        b'\t' => Key::Tab,
+        0x01 => Key::Home,
+        0x02 => Key::Left,
+        0x03 | 0x07 => Key::Cancel,
+        0x04 => Key::Eof,
+        0x05 => Key::End,
+        0x06 => Key::Right,
+        0x0b => Key::KillEnd,
+        0x0c => Key::ClearScreen,
+        0x0e => Key::Down,
+        0x10 => Key::Up,
+        0x12 => Key::Search,
+        0x15 => Key::KillStart,
+        0x17 => Key::KillWord,
+        0x1b => read_escape(input)?,
+        byte if byte < 0x20 => Key::Ignored,
+        byte => read_char(input, byte)?,
+    };
+    Ok(Some(key))
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// Rest of a UTF-8 character starting with first
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Key> {
+    let len = match first {
+        0xf0.. => 4,
+        0xe0.. => 3,
+        0xc0.. => 2,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte(input)? {
+            Some(byte) => bytes.push(byte),
+            None => break,
+        }
+    }
+    Ok(std::str::from_utf8(&bytes).ok()
+        .and_then(|text| text.chars().next())
+        .map_or(Key::Ignored, Key::Char))
+}
+
+// Keys sent as ESC [ or ESC O followed by parameters and a final letter
+fn read_escape(input: &mut impl Read) -> io::Result<Key> {
+    match read_byte(input)? {
+        Some(b'[' | b'O') => {},
+        _ => return Ok(Key::Ignored),
+    }
+
+    let mut params = String::new();
+    let last = loop {
+        match read_byte(input)? {
+            Some(byte @ (b'0'..=b'9' | b';')) => params.push(byte as char),
+            Some(byte) => break byte,
+            None => return Ok(Key::Ignored),
+        }
+    };
+
+    // Ctrl with an arrow key moves by word, ESC [ 1 ; 5 D
+    let ctrl = params.ends_with(";5");
+    Ok(match (last, params.as_str()) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) if ctrl => Key::WordRight,
+        (b'D', _) if ctrl => Key::WordLeft,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) | (b'~', "1" | "7") => Key::Home,
+        (b'F', _) | (b'~', "4" | "8") => Key::End,
+        (b'~', "3") => Key::Delete,
+        _ => Key::Ignored,
+    })
+}
+
+// Text being edited and the cursor position in characters
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Line {
+    pub chars: Vec<char>,
+    pub cursor: usize,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    // Replaces the text, with the cursor at the end
+    pub fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.cursor = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    // Start of the word before the cursor, skipping spaces first
+    fn word_start(&self) -> usize {
+        let mut i = self.cursor;
+        while i > 0 && !self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        while i > 0 && self.chars[i - 1].is_alphanumeric() {
+            i -= 1;
+        }
+        i
+    }
+
+    fn word_end(&self) -> usize {
+        let mut i = self.cursor;
+        while i < self.chars.len() && !self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        while i < self.chars.len() && self.chars[i].is_alphanumeric() {
+            i += 1;
+        }
+        i
+    }
+}
+
+// Incremental search back through the history
+#[derive(Debug, Default)]
+struct Search {
+    query: String,
+    // History index of the line shown
+    found: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Continue,
+    Submit(String),
+    // Line abandoned with Ctrl-C
+    Cancel,
+    Eof,
+    ClearScreen,
+}
+
+// Editing state of the line being read
+#[derive(Debug, Default)]
+pub(crate) struct State {
+    pub line: Line,
+    // History index of the recalled line, the history length for a new line
+    recall: usize,
+    // New line put aside while recalling history
+    draft: String,
+    search: Option<Search>,
+}
+
+impl State {
+    pub fn new(history: &[String]) -> State {
+        State { recall: history.len(), ..State::default() }
+    }
+
+    pub fn apply(&mut self, key: Key, history: &[String]) -> Outcome {
+        if self.search.is_some() && !self.apply_search(key, history) {
+            return Outcome::Continue;
+        }
+
+        let line = &mut self.line;
+        match key {
+            Key::Char(c) => line.insert(c),
+            Key::Enter => return Outcome::Submit(line.text()),
+            Key::Backspace => line.backspace(),
+            Key::Delete => line.delete(),
+            Key::Left => line.cursor = line.cursor.saturating_sub(1),
+            Key::Right => line.cursor = (line.cursor + 1).min(line.chars.len()),
+            Key::WordLeft => line.cursor = line.word_start(),
+            Key::WordRight => line.cursor = line.word_end(),
+            Key::Home => line.cursor = 0,
+            Key::End => line.cursor = line.chars.len(),
+            Key::KillEnd => line.chars.truncate(line.cursor),
+            Key::KillStart => {
+                line.chars.drain(..line.cursor);
+                line.cursor = 0;
+            },
+            Key::KillWord => {
+                let start = line.word_start();
+                line.chars.drain(start..line.cursor);
+                line.cursor = start;
+            },
+            Key::Up if self.recall > 0 => {
+                if self.recall == history.len() {
+                    self.draft = line.text();
+                }
+                self.recall -= 1;
+                line.set(&history[self.recall]);
+            },
+            Key::Down if self.recall < history.len() => {
+                self.recall += 1;
+                line.set(history.get(self.recall).unwrap_or(&self.draft));
+            },
+            Key::Search => self.search = Some(Search::default()),
+            Key::Cancel => return Outcome::Cancel,
+            Key::Eof if line.chars.is_empty() => return Outcome::Eof,
+            Key::Eof => line.delete(),
+            Key::ClearScreen => return Outcome::ClearScreen,
+            Key::Up | Key::Down | Key::Tab | Key::Ignored => {},
+        }
+        Outcome::Continue
+    }
+
+    // Handles a key while searching, returns whether the search ended and the
+    // key still applies to the line
+    fn apply_search(&mut self, key: Key, history: &[String]) -> bool {
+        let Some(search) = &mut self.search else {
+            return true;
+        };
+
+        match key {
+            Key::Char(c) => {
+                search.query.push(c);
+                // A longer query may still match the line shown
+                let from = search.found.map_or(history.len(), |found| found + 1);
+                search.found = find(history, &search.query, from);
+            },
+            Key::Search => {
+                let from = search.found.unwrap_or(history.len());
+                if let Some(found) = find(history, &search.query, from) {
+                    search.found = Some(found);
+                }
+            },
+            Key::Backspace => {
+                search.query.pop();
+                search.found = find(history, &search.query, history.len());
+            },
+            Key::Cancel => self.search = None,
+            // Any other key takes the line found and applies to it
+            _ => {
+                if let Some(found) = search.found {
+                    self.line.set(&history[found]);
+                    self.recall = found;
+                }
+                self.search = None;
+                return true;
+            },
+        }
+        false
+    }
+
+    pub fn searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    // Completes the word from start to the cursor, returns what to list when the
+    // candidates have nothing more in common
+    pub fn complete(&mut self, start: usize, candidates: &[Candidate]) -> Option<Vec<String>> {
+        let text = match candidates {
+            [] => return None,
+            [only] => only.text.clone(),
+            _ => completion::common_prefix(candidates),
+        };
+
+        let len = text.chars().count();
+        if candidates.len() > 1 && len <= self.line.cursor - start {
+            return Some(candidates.iter().map(|candidate| candidate.display.clone()).collect());
+        }
+        self.line.chars.splice(start..self.line.cursor, text.chars());
+        self.line.cursor = start + len;
+        None
+    }
+
+    // Text to show after returning to the start of the line, and the column of
+    // the cursor within it. With colors the line is highlighted as it is typed
+    pub fn render(&self, prompt: &str, history: &[String], colors: bool) -> (String, usize) {
+        match &self.search {
+            Some(search) => {
+                let label = format!("(reverse-i-search)`{}': ", search.query);
+                let found = search.found.map_or("", |found| history[found].as_str());
+                let column = found.find(&search.query).map_or(0, |byte| found[..byte].chars().count());
+                (format!("{label}{found}"), label.chars().count() + column)
+            },
+            None => {
+                let text = if colors { highlight::highlight(&self.line.text(), self.line.cursor) } else { self.line.text() };
+                (format!("{prompt}{text}"), prompt.chars().count() + self.line.cursor)
+            },
+        }
+    }
+}
+
+// Most recent history line before index from that contains query
+fn find(history: &[String], query: &str, from: usize) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    history[..from.min(history.len())].iter().rposition(|line| line.contains(query))
+}
+
+// Reads lines with editing keys and history when stdin and stdout are terminals
+pub(crate) struct Editor {
+    history: Vec<String>,
+    file: Option<PathBuf>,
+    // Whether the terminal takes colors
+    colors: bool,
+    // Whether the line is highlighted while it is typed
+    pub highlight: bool,
+}
+
+impl Editor {
+    pub fn new() -> Editor {
+        let file = history_file();
+        let mut history: Vec<String> = file.as_ref()
+            .and_then(|file| fs::read_to_string(file).ok())
+            .map(|text| text.lines().filter(|line| !line.trim().is_empty()).map(String::from).collect())
+            .unwrap_or_default();
+
+        // Keep the file from growing without bound
+        if history.len() > HISTORY_LIMIT {
+            history.drain(..history.len() - HISTORY_LIMIT);
+            if let Some(file) = &file {
+                let _ = fs::write(file, history.iter().map(|line| format!("{line}\n")).collect::<String>());
+            }
+        }
+
+        Editor { history, file, colors: highlight::color_enabled(), highlight: true }
+    }
+
+    // Next line without its line break, None at the end of input. Tab completes
+    // the word before the cursor with what complete finds for the line and cursor
+    pub fn read_line(&mut self, prompt: &str, complete: Completer) -> Option<String> {
+        let raw = if io::stdout().is_terminal() { RawMode::enable() } else { None };
+        let line = match raw {
+            Some(_) => self.read_edited(prompt, complete),
+            None => read_plain(prompt),
+        }?;
+
+        self.add_history(&line);
+        Some(line)
+    }
+
+    fn read_edited(&self, prompt: &str, complete: Completer) -> Option<String> {
+        let mut state = State::new(&self.history);
+        let mut stdin = io::stdin().lock();
+        let mut stdout = io::stdout().lock();
+
+        refresh(&mut stdout, &state.render(prompt, &self.history, self.colors && self.highlight));
+        loop {
+            let key = read_key(&mut stdin).ok().flatten()?;
+            let outcome = if key == Key::Tab && !state.searching() {
+                let (start, candidates) = complete(&state.line.text(), state.line.cursor);
+                if let Some(listing) = state.complete(start, &candidates) {
+                    let _ = write!(stdout, "\r\n{}\r\n", listing.join("\r\n"));
+                }
+                Outcome::Continue
+            }
+            else {
+                state.apply(key, &self.history)
+            };
+            match outcome {
+                Outcome::Continue => {},
+                Outcome::Submit(line) => {
+                    let _ = write!(stdout, "\r\n");
+                    let _ = stdout.flush();
+                    return Some(line);
+                },
+                Outcome::Cancel => {
+                    let _ = write!(stdout, "^C\r\n");
+                    state = State::new(&self.history);
+                },
+                Outcome::Eof => return None,
+                Outcome::ClearScreen => {
+                    let _ = write!(stdout, "\x1b[H\x1b[2J");
+                },
+            }
+            refresh(&mut stdout, &state.render(prompt, &self.history, self.colors && self.highlight));
+        }
+    }
+
+    fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.history.push(line.to_string());
+
+        // History is a convenience, failing to save it is not worth reporting
+        if let Some(file) = &self.file {
+            if let Some(dir) = file.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            if let Ok(mut opened) = OpenOptions::new().create(true).append(true).open(file) {
+                let _ = writeln!(opened, "{line}");
+            }
+        }
+    }
+}
+
+// Redraws the line, clearing what was left of the previous one
+fn refresh(out: &mut impl Write, (text, column): &(String, usize)) {
+    let _ = write!(out, "\r{text}\x1b[K\r");
+    if *column > 0 {
+        let _ = write!(out, "\x1b[{column}C");
+    }
+    let _ = out.flush();
+}
+
+fn read_plain(prompt: &str) -> Option<String> {
+    print!("{prompt}");
+    io::stdout().flush().unwrap();
+
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+    }
+}
+
+// ~/.local/share/calculator/history, or under XDG_DATA_HOME when it is set
+fn history_file() -> Option<PathBuf> {
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+    Some(data.join("calculator").join("history"))
+}
@@ -0,0 +1,37 @@
+use std::fmt;
+
+use crate::token::split_formatted;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CalcError {
+    pub message: String,
+    // Index of the offending token, when the error can be tied to one
+    pub position: Option<usize>,
+}
+
+impl CalcError {
+    pub fn new(message: impl Into<String>) -> CalcError {
+        CalcError { message: message.into(), position: None }
+    }
+
+    pub fn at(position: usize, message: impl Into<String>) -> CalcError {
+        CalcError { message: message.into(), position: Some(position) }
+    }
+
+    // Column of the offending token within a space separated expression
+    pub fn column(&self, formatted: &str) -> Option<usize> {
+        let position = self.position?;
+        let column = split_formatted(formatted).into_iter()
+            .take(position)
+            .map(|part| part.chars().count() + 1)
+            .sum();
+
+        Some(column)
+    }
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::error::CalcError;
+use crate::functions::{self, AngleUse, Apply};
+use crate::notation;
+use crate::parser::Expr;
+use crate::settings::{AngleUnit, NumberFormat};
+use crate::token::TokenType;
+use crate::{op_add, op_sub, op_mul, op_div, op_exp};
+
+// Values of names other than the built in constants, such as spreadsheet cells
+pub(crate) type Scope = HashMap<String, Value>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Number(f64),
+    Bool(bool),
+    Text(String),
+}
+
+impl Value {
+    pub fn as_number(&self) -> Result<f64, CalcError> {
+        match self {
+            Value::Number(number) => Ok(*number),
+            _ => Err(CalcError::new(format!("Expected a number but found {self}"))),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool, CalcError> {
+        match self {
+            Value::Bool(value) => Ok(*value),
+            _ => Err(CalcError::new(format!("Condition must be true or false, found {self}"))),
+        }
+    }
+
+    pub fn as_text(&self) -> Result<&str, CalcError> {
+        match self {
+            Value::Text(text) => Ok(text),
+            _ => Err(CalcError::new(format!("Expected text but found {self}"))),
+        }
+    }
+
+    // Literal expression standing for this value
+    pub fn into_expr(self) -> Expr {
+        match self {
+            Value::Number(number) => Expr::Number(number),
+            Value::Bool(value) => Expr::Bool(value),
+            Value::Text(text) => Expr::Text(text),
+        }
+    }
+
+    // Value as shown to users, numbers follow the chosen number format
+    pub fn display(&self, number: &NumberFormat) -> String {
+        match self {
+            Value::Number(value) => notation::format_number(*value, number),
+            Value::Bool(value) => value.to_string(),
+            Value::Text(text) => text.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{number}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Text(text) => write!(f, "\"{text}\""),
+        }
+    }
+}
+
+// Angles of trigonometric functions are in the given unit
+pub(crate) fn evaluate(expr: &Expr, scope: &Scope, angle: AngleUnit) -> Result<Value, CalcError> {
+    match expr {
+        Expr::Number(number) => Ok(Value::Number(*number)),
+        Expr::Text(text) => Ok(Value::Text(text.clone())),
+        Expr::Bool(value) => Ok(Value::Bool(*value)),
+        Expr::Name(name) => {
+            if let Some(value) = scope.get(name) {
+                return Ok(value.clone());
+            }
+            functions::find_constant(name)
+                .map(Value::Number)
+                .ok_or_else(|| CalcError::new(format!("Unknown name: {name}")))
+        },
+        Expr::Call(name, args) => {
+            let function = functions::find_function(name)
+                .ok_or_else(|| CalcError::new(format!("Unknown function: {name}")))?;
+            match function.apply {
+                Apply::Number(apply) => {
+                    let mut args = args.iter()
+                        .map(|arg| evaluate(arg, scope, angle)?.as_number())
+                        .collect::<Result<Vec<f64>, CalcError>>()?;
+                    // The functions themselves work in radians
+                    match function.angle() {
+                        Some(AngleUse::Takes) => {
+                            args[0] = angle.to_radians(args[0]);
+                            Ok(Value::Number(apply(&args)))
+                        },
+                        Some(AngleUse::Gives) => Ok(Value::Number(angle.of_radians(apply(&args)))),
+                        None => Ok(Value::Number(apply(&args))),
+                    }
+                },
+                Apply::Value(apply) => {
+                    let args = args.iter()
+                        .map(|arg| evaluate(arg, scope, angle))
+                        .collect::<Result<Vec<Value>, CalcError>>()?;
+                    apply(&args)
+                },
+            }
+        },
+        Expr::Negate(operand) => {
+            let a = evaluate(operand, scope, angle)?.as_number()?;
+            Ok(Value::Number(-a))
+        },
+        Expr::Binary(ttype, lhs, rhs) => {
+            let a = evaluate(lhs, scope, angle)?;
+            let b = evaluate(rhs, scope, angle)?;
+            apply_binary(*ttype, &a, &b)
+        },
+        Expr::Conditional(condition, then, otherwise) => {
+            // Only the selected branch is evaluated
+            if evaluate(condition, scope, angle)?.as_bool()? {
+                evaluate(then, scope, angle)
+            }
+            else {
+                evaluate(otherwise, scope, angle)
+            }
+        },
+    }
+}
+
+fn apply_binary(ttype: TokenType, a: &Value, b: &Value) -> Result<Value, CalcError> {
+    // Equality works on any pair of matching values
+    match ttype {
+        TokenType::Equal => return Ok(Value::Bool(a == b)),
+        TokenType::NotEqual => return Ok(Value::Bool(a != b)),
+        // Joins the values as text, numbers as they are shown by default
+        TokenType::Concat => {
+            let number = NumberFormat::default();
+            return Ok(Value::Text(format!("{}{}", a.display(&number), b.display(&number))));
+        },
+        _ => {},
+    }
+
+    let a = a.as_number()?;
+    let b = b.as_number()?;
+    let value = match ttype {
+        TokenType::Addition => Value::Number(op_add(a, b)),
+        TokenType::Subtract => Value::Number(op_sub(a, b)),
+        TokenType::Multiply => Value::Number(op_mul(a, b)),
+        TokenType::Division => {
+            if b == 0. {
+                return Err(CalcError::new("Division by zero"));
+            }
+            Value::Number(op_div(a, b))
+        },
+        TokenType::Exponent => Value::Number(op_exp(a, b)),
+        TokenType::Less => Value::Bool(a < b),
+        TokenType::LessEqual => Value::Bool(a <= b),
+        TokenType::Greater => Value::Bool(a > b),
+        TokenType::GreaterEqual => Value::Bool(a >= b),
+        _ => return Err(CalcError::new(format!("Unknown operator: {ttype:?}"))),
+    };
+
+    Ok(value)
+}
@@ -0,0 +1,120 @@
+use crate::error::CalcError;
+use crate::eval::{self, Scope, Value};
+use crate::parser::Expr;
+use crate::printer;
+use crate::settings::AngleUnit;
+use crate::token::TokenType;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Step {
+    // Whole expression after this step
+    pub expression: String,
+    // Operation that was carried out, such as "multiplication: 3 * 4 = 12"
+    pub rule: String,
+}
+
+// Reduces the expression one operation at a time in evaluation order, the
+// steps taken so far are kept when a later one fails
+pub(crate) fn explain(expr: &Expr, scope: &Scope, angle: AngleUnit) -> (Vec<Step>, Result<Value, CalcError>) {
+    let mut expr = expr.clone();
+    let mut steps = Vec::<Step>::new();
+
+    loop {
+        match reduce(&mut expr, scope, angle) {
+            Ok(Some(rule)) => steps.push(Step { expression: printer::print(&expr), rule }),
+            Ok(None) => break,
+            Err(error) => return (steps, Err(error)),
+        }
+    }
+
+    let result = eval::evaluate(&expr, scope, angle);
+    (steps, result)
+}
+
+// Starting expression followed by one line per step, rules lined up on the right
+pub(crate) fn render(start: &str, steps: &[Step]) -> String {
+    let width = steps.iter()
+        .map(|step| step.expression.chars().count())
+        .chain([start.chars().count()])
+        .max()
+        .unwrap_or(0);
+
+    let mut output = format!("  {start}\n");
+    for step in steps {
+        output.push_str(&format!("→ {:width$}   {}\n", step.expression, step.rule));
+    }
+
+    output
+}
+
+// Carries out the first operation whose operands are all values
+fn reduce(expr: &mut Expr, scope: &Scope, angle: AngleUnit) -> Result<Option<String>, CalcError> {
+    // Operands first, left to right, the same order the evaluator uses
+    let inner = match expr {
+        Expr::Number(_) | Expr::Text(_) | Expr::Bool(_) => return Ok(None),
+        Expr::Name(_) => None,
+        Expr::Negate(operand) => {
+            let inner = reduce(operand, scope, angle)?;
+            // A negated number already reads as a negative number, no step of its own
+            if let Expr::Number(number) = **operand {
+                *expr = Expr::Number(-number);
+                return Ok(inner);
+            }
+            inner
+        },
+        Expr::Binary(_, lhs, rhs) => match reduce(lhs, scope, angle)? {
+            Some(rule) => Some(rule),
+            None => reduce(rhs, scope, angle)?,
+        },
+        Expr::Call(_, args) => {
+            let mut inner = None;
+            for arg in args {
+                inner = reduce(arg, scope, angle)?;
+                if inner.is_some() {
+                    break;
+                }
+            }
+            inner
+        },
+        // Only the condition, the branch not taken is never evaluated
+        Expr::Conditional(condition, _, _) => reduce(condition, scope, angle)?,
+    };
+    if inner.is_some() {
+        return Ok(inner);
+    }
+
+    if let Expr::Conditional(condition, then, otherwise) = expr {
+        let (branch, rule) = if eval::evaluate(condition, scope, angle)?.as_bool()? {
+            (then, "condition is true, take the first branch")
+        }
+        else {
+            (otherwise, "condition is false, take the second branch")
+        };
+        *expr = std::mem::replace(branch.as_mut(), Expr::Bool(false));
+        return Ok(Some(String::from(rule)));
+    }
+
+    let literal = eval::evaluate(expr, scope, angle)?.into_expr();
+    let rule = format!("{}: {} = {}", rule_name(expr), printer::print(expr), printer::print(&literal));
+    *expr = literal;
+
+    Ok(Some(rule))
+}
+
+fn rule_name(expr: &Expr) -> &'static str {
+    match expr {
+        Expr::Name(_) => "constant",
+        Expr::Call(..) => "function",
+        Expr::Negate(_) => "negation",
+        Expr::Binary(ttype, _, _) => match ttype {
+            TokenType::Addition => "addition",
+            TokenType::Subtract => "subtraction",
+            TokenType::Multiply => "multiplication",
+            TokenType::Division => "division",
+            TokenType::Exponent => "exponent",
+            TokenType::Concat => "concatenation",
+            _ => "comparison",
+        },
+        _ => "value",
+    }
+}
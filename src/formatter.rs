@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+use crate::error::CalcError;
+use crate::functions::UserFunction;
+use crate::printer;
+use crate::repl;
+use crate::settings::{Settings, Style};
+use crate::{error_pointer, parse_input};
+
+// Rewrites an expression in canonical form, the same form it comes back as
+// when formatted again. Script lines that define a name keep it and have their
+// formula rewritten, commands such as :set are kept as they are. Functions a
+// line defines go into settings, so that later lines may call them
+pub(crate) fn format_expression(input: &str, settings: &mut Settings, style: &Style) -> (String, Result<String, CalcError>) {
+    let input = input.trim();
+    if input.starts_with(':') {
+        return (input.to_string(), Ok(input.to_string()));
+    }
+    if let Some((name, params, formula)) = repl::split_function(input) {
+        let (formatted, body) = parse_input(formula, settings);
+        let Ok(body) = body else {
+            return (formatted, body.map(|_| String::new()));
+        };
+        let function = UserFunction { name: name.to_string(), params: params.iter().map(|param| param.to_string()).collect(), body, formula: formatted.clone() };
+        let rewritten = format!("{} = {}", function.signature(), printer::print_styled(&function.body, style));
+        settings.functions.insert(name.to_string(), function);
+        return (formatted, Ok(rewritten));
+    }
+    if let Some((name, formula)) = repl::split_definition(input) {
+        let (formatted, expr) = parse_input(formula, settings);
+        return (formatted, expr.map(|expr| format!("{name} = {}", printer::print_styled(&expr, style))));
+    }
+
+    let (formatted, expr) = parse_input(input, settings);
+    (formatted, expr.map(|expr| printer::print_styled(&expr, style)))
+}
+
+// Rewrites every file in turn to stdout, "-" reads stdin. With check nothing is
+// printed and lines that would change are reported instead. Returns whether
+// every line could be rewritten, and with check whether none would change
+pub(crate) fn rewrite_files(files: &[String], rewrite: &mut impl FnMut(&str) -> (String, Result<String, CalcError>), check: bool) -> bool {
+    let mut stdout = io::stdout().lock();
+    let mut stderr = io::stderr().lock();
+    let mut succeeded = true;
+
+    for file in files {
+        let result = if file == "-" {
+            rewrite_lines(io::stdin().lock(), "<stdin>", rewrite, check, &mut stdout, &mut stderr)
+        }
+        else {
+            match File::open(file) {
+                Ok(opened) => rewrite_lines(BufReader::new(opened), file, rewrite, check, &mut stdout, &mut stderr),
+                Err(error) => {
+                    eprintln!("ERROR: Could not open {file}: {error}");
+                    return false;
+                },
+            }
+        };
+
+        match result {
+            Ok(rewritten) => succeeded &= rewritten,
+            Err(error) => {
+                eprintln!("ERROR: Could not read {file}: {error}");
+                return false;
+            },
+        }
+    }
+
+    succeeded
+}
+
+// Blank lines and # comments are kept as they are, lines that fail to parse
+// are kept too so no formula is lost
+pub(crate) fn rewrite_lines(
+    reader: impl BufRead,
+    source: &str,
+    rewrite: &mut impl FnMut(&str) -> (String, Result<String, CalcError>),
+    check: bool,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> io::Result<bool> {
+    let mut succeeded = true;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            if !check {
+                writeln!(out, "{line}")?;
+            }
+            continue;
+        }
+
+        let (formatted, result) = rewrite(trimmed);
+        match result {
+            Ok(rewritten) if check => {
+                if rewritten != line {
+                    writeln!(err, "{source}:{}: not formatted, expected: {rewritten}", index + 1)?;
+                    succeeded = false;
+                }
+            },
+            Ok(rewritten) => writeln!(out, "{rewritten}")?,
+            Err(error) => {
+                write!(err, "{source}:{}: ERROR: {error}\n{}", index + 1, error_pointer(&formatted, &error))?;
+                if !check {
+                    writeln!(out, "{line}")?;
+                }
+                succeeded = false;
+            },
+        }
+    }
+
+    Ok(succeeded)
+}
@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, HashMap};
+use std::f64::consts;
+
+use crate::error::CalcError;
+use crate::eval::Value;
+use crate::notation;
+use crate::parser::Expr;
+use crate::settings::NumberFormat;
+
+pub(crate) enum Apply {
+    // Every argument must be a number
+    Number(fn(&[f64]) -> f64),
+    // Arguments of any type, checked by the function itself
+    Value(fn(&[Value]) -> Result<Value, CalcError>),
+}
+
+// How a function works with angles, which are in the angle unit setting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AngleUse {
+    // The first argument is an angle
+    Takes,
+    // The result is an angle
+    Gives,
+}
+
+pub(crate) struct Function {
+    pub name: &'static str,
+    pub params: &'static [&'static str],
+    // One line description for :help
+    pub summary: &'static str,
+    pub apply: Apply,
+}
+
+impl Function {
+    // Call form shown to users, such as atan2(y, x)
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.params.join(", "))
+    }
+
+    // Params ending in ... take any number of values after the named ones
+    pub fn variadic(&self) -> bool {
+        self.params.last() == Some(&"...")
+    }
+
+    // Values the function is given in RPN, and the fewest it takes otherwise
+    pub fn arity(&self) -> usize {
+        self.params.len() - usize::from(self.variadic())
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count == self.arity() || (self.variadic() && count > self.arity())
+    }
+
+    // Number of arguments for errors, such as "at least 2"
+    pub fn takes(&self) -> String {
+        if self.variadic() { format!("at least {}", self.arity()) } else { self.arity().to_string() }
+    }
+
+    pub fn angle(&self) -> Option<AngleUse> {
+        match self.name {
+            "sin" | "cos" | "tan" => Some(AngleUse::Takes),
+            "asin" | "acos" | "atan" | "atan2" => Some(AngleUse::Gives),
+            _ => None,
+        }
+    }
+}
+
+// Function defined in a session, such as f(x) = x^2 + 1
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct UserFunction {
+    pub name: String,
+    pub params: Vec<String>,
+    // Calls of other defined functions are kept as calls
+    pub body: Expr,
+    // Body in plain syntax
+    pub formula: String,
+}
+
+impl UserFunction {
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.params.join(", "))
+    }
+}
+
+// Functions defined in a session by name
+pub(crate) type Defined = BTreeMap<String, UserFunction>;
+
+pub(crate) const CONSTANTS: &[(&str, f64)] = &[
+    ("pi",  consts::PI),
+    ("e",   consts::E),
+    ("tau", consts::TAU),
+];
+
+pub(crate) const FUNCTIONS: &[Function] = &[
+    Function { name: "sqrt",  params: &["x"],      summary: "Square root", apply: Apply::Number(|args| args[0].sqrt()) },
+    Function { name: "cbrt",  params: &["x"],      summary: "Cube root", apply: Apply::Number(|args| args[0].cbrt()) },
+    Function { name: "abs",   params: &["x"],      summary: "Absolute value", apply: Apply::Number(|args| args[0].abs()) },
+    Function { name: "floor", params: &["x"],      summary: "Largest whole number not above x", apply: Apply::Number(|args| args[0].floor()) },
+    Function { name: "ceil",  params: &["x"],      summary: "Smallest whole number not below x", apply: Apply::Number(|args| args[0].ceil()) },
+    Function { name: "round", params: &["x"],      summary: "Nearest whole number, halves away from zero", apply: Apply::Number(|args| args[0].round()) },
+    Function { name: "exp",   params: &["x"],      summary: "e to the power x", apply: Apply::Number(|args| args[0].exp()) },
+    Function { name: "ln",    params: &["x"],      summary: "Natural logarithm", apply: Apply::Number(|args| args[0].ln()) },
+    Function { name: "log",   params: &["x"],      summary: "Base 10 logarithm", apply: Apply::Number(|args| args[0].log10()) },
+    Function { name: "sin",   params: &["x"],      summary: "Sine of the angle x", apply: Apply::Number(|args| args[0].sin()) },
+    Function { name: "cos",   params: &["x"],      summary: "Cosine of the angle x", apply: Apply::Number(|args| args[0].cos()) },
+    Function { name: "tan",   params: &["x"],      summary: "Tangent of the angle x", apply: Apply::Number(|args| args[0].tan()) },
+    Function { name: "asin",  params: &["x"],      summary: "Arcsine as an angle", apply: Apply::Number(|args| args[0].asin()) },
+    Function { name: "acos",  params: &["x"],      summary: "Arccosine as an angle", apply: Apply::Number(|args| args[0].acos()) },
+    Function { name: "atan",  params: &["x"],      summary: "Arctangent as an angle", apply: Apply::Number(|args| args[0].atan()) },
+    Function { name: "atan2", params: &["y", "x"], summary: "Angle of the point (x, y)", apply: Apply::Number(|args| args[0].atan2(args[1])) },
+    Function { name: "min",   params: &["a", "b", "..."], summary: "Smallest of the values", apply: Apply::Number(|args| args.iter().copied().reduce(f64::min).unwrap_or(f64::NAN)) },
+    Function { name: "max",   params: &["a", "b", "..."], summary: "Largest of the values", apply: Apply::Number(|args| args.iter().copied().reduce(f64::max).unwrap_or(f64::NAN)) },
+    Function { name: "sum",   params: &["a", "b", "..."], summary: "Total of the values", apply: Apply::Number(|args| args.iter().sum()) },
+    Function { name: "average", params: &["a", "b", "..."], summary: "Mean of the values", apply: Apply::Number(|args| args.iter().sum::<f64>() / args.len() as f64) },
+    Function { name: "concat", params: &["a", "b", "..."], summary: "The values joined as text, as a & b", apply: Apply::Value(concat) },
+    Function { name: "bool",  params: &["x"],      summary: "x as a condition, where any number but 0 is true", apply: Apply::Value(truth) },
+    Function { name: "format", params: &["x", "spec"], summary: "x as text in the number format spec, such as \"sci, sig=3\"", apply: Apply::Value(format_value) },
+];
+
+fn truth(args: &[Value]) -> Result<Value, CalcError> {
+    match &args[0] {
+        Value::Number(number) => Ok(Value::Bool(*number != 0.)),
+        value => value.as_bool().map(Value::Bool),
+    }
+}
+
+fn concat(args: &[Value]) -> Result<Value, CalcError> {
+    let number = NumberFormat::default();
+    Ok(Value::Text(args.iter().map(|arg| arg.display(&number)).collect()))
+}
+
+fn format_value(args: &[Value]) -> Result<Value, CalcError> {
+    let x = args[0].as_number()?;
+    let format = NumberFormat::from_spec(args[1].as_text()?)?;
+    Ok(Value::Text(notation::format_number(x, &format)))
+}
+
+pub(crate) fn find_function(name: &str) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|function| function.name == name)
+}
+
+// Expression with each call of a defined function replaced by its body, in which
+// the parameters stand for the arguments
+pub(crate) fn expand(expr: &Expr, defined: &Defined) -> Expr {
+    substitute(expr, defined, &HashMap::new())
+}
+
+fn substitute(expr: &Expr, defined: &Defined, args: &HashMap<&str, Expr>) -> Expr {
+    let replace = |expr: &Expr| Box::new(substitute(expr, defined, args));
+    match expr {
+        Expr::Name(name) => args.get(name.as_str()).cloned().unwrap_or_else(|| expr.clone()),
+        Expr::Call(name, call_args) => {
+            let call_args: Vec<Expr> = call_args.iter().map(|arg| substitute(arg, defined, args)).collect();
+            match defined.get(name) {
+                // Names in the body other than parameters are not the caller's
+                Some(function) => {
+                    let bound = function.params.iter().map(String::as_str).zip(call_args).collect();
+                    substitute(&function.body, defined, &bound)
+                },
+                None => Expr::Call(name.clone(), call_args),
+            }
+        },
+        Expr::Negate(operand) => Expr::Negate(replace(operand)),
+        Expr::Binary(ttype, lhs, rhs) => Expr::Binary(*ttype, replace(lhs), replace(rhs)),
+        Expr::Conditional(condition, then, otherwise) => Expr::Conditional(replace(condition), replace(then), replace(otherwise)),
+        Expr::Number(_) | Expr::Text(_) | Expr::Bool(_) => expr.clone(),
+    }
+}
+
+pub(crate) fn find_constant(name: &str) -> Option<f64> {
+    CONSTANTS.iter()
+        .find(|(constant, _)| *constant == name)
+        .map(|(_, value)| *value)
+}
@@ -0,0 +1,127 @@
+use std::io::{self, IsTerminal};
+
+use crate::token::TokenType;
+use std::ops::Range;
+
+use crate::{parse_tokens, replace_unicode, split_lexeme_spans, superscript_digit};
+
+const RESET: &str = "\x1b[0m";
+// Brackets that do not pair up, and lexemes that are not tokens at all
+const ERROR: &str = "\x1b[1;31m";
+// Both brackets of the pair the cursor is on
+const MATCH: &str = "\x1b[1;7m";
+
+// Colors only go to a terminal, and not when NO_COLOR is set to anything
+pub(crate) fn color_enabled() -> bool {
+    io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+}
+
+fn color(ttype: TokenType) -> Option<&'static str> {
+    match ttype {
+        TokenType::Number => Some("\x1b[36m"),
+        TokenType::Text => Some("\x1b[32m"),
+        TokenType::Identifier => Some("\x1b[34m"),
+        TokenType::If | TokenType::Then | TokenType::Else => Some("\x1b[35m"),
+        TokenType::Addition | TokenType::Subtract | TokenType::Multiply | TokenType::Division |
+        TokenType::Exponent | TokenType::Equal | TokenType::NotEqual | TokenType::Less |
+        TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual | TokenType::Concat |
+        TokenType::Question | TokenType::Colon | TokenType::Root => Some("\x1b[33m"),
+        _ => None,
+    }
+}
+
+// Bracket kind and whether it opens, bars can do either and are left out
+fn bracket(ttype: TokenType) -> Option<(u8, bool)> {
+    match ttype {
+        TokenType::ParenOpen => Some((0, true)),
+        TokenType::ParenClose => Some((0, false)),
+        TokenType::BracketOpen => Some((1, true)),
+        TokenType::BracketClose => Some((1, false)),
+        TokenType::BraceOpen => Some((2, true)),
+        TokenType::BraceClose => Some((2, false)),
+        TokenType::FloorOpen => Some((3, true)),
+        TokenType::FloorClose => Some((3, false)),
+        TokenType::CeilOpen => Some((4, true)),
+        TokenType::CeilClose => Some((4, false)),
+        _ => None,
+    }
+}
+
+// The line with ANSI colors by token kind. The bracket at the cursor, or just
+// before it, is shown together with the one it pairs with
+pub(crate) fn highlight(line: &str, cursor: usize) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let spans = split_superscripts(split_lexeme_spans(line));
+    let types: Vec<Option<TokenType>> = spans.iter()
+        .map(|(_, lexeme)| {
+            // An exponent such as ² is shown like the number it stands for
+            if lexeme.chars().all(|c| superscript_digit(c).is_some()) {
+                return Some(TokenType::Number);
+            }
+            parse_tokens(&replace_unicode(lexeme)).ok().and_then(|tokens| tokens.first().map(|token| token.ttype))
+        })
+        .collect();
+
+    // Partner of every bracket, None for the ones that do not pair up
+    let mut partner = vec![None::<usize>; spans.len()];
+    let mut open = Vec::<(usize, u8)>::new();
+    for (i, ttype) in types.iter().enumerate() {
+        match ttype.and_then(bracket) {
+            Some((kind, true)) => open.push((i, kind)),
+            Some((kind, false)) => {
+                if let Some(&(opening, open_kind)) = open.last() && open_kind == kind {
+                    open.pop();
+                    partner[i] = Some(opening);
+                    partner[opening] = Some(i);
+                }
+            },
+            None => {},
+        }
+    }
+
+    let at_cursor = |offset: usize| cursor.checked_sub(offset)
+        .and_then(|position| spans.iter().position(|(range, _)| range.contains(&position)))
+        .filter(|&i| types[i].and_then(bracket).is_some() && partner[i].is_some());
+    let matched = at_cursor(0).or_else(|| at_cursor(1))
+        .map(|i| [Some(i), partner[i]]);
+
+    let mut output = String::new();
+    let mut next = 0;
+    for (i, (range, _)) in spans.iter().enumerate() {
+        output.extend(&chars[next..range.start]);
+        let style = match types[i] {
+            _ if matched.is_some_and(|pair| pair.contains(&Some(i))) => Some(MATCH),
+            None => Some(ERROR),
+            Some(ttype) if bracket(ttype).is_some() && partner[i].is_none() => Some(ERROR),
+            Some(ttype) => color(ttype),
+        };
+        match style {
+            Some(style) => output.push_str(&format!("{style}{}{RESET}", chars[range.clone()].iter().collect::<String>())),
+            None => output.extend(&chars[range.clone()]),
+        }
+        next = range.end;
+    }
+    output.extend(&chars[next.min(chars.len())..]);
+
+    output
+}
+
+// Superscripts may be glued to the lexeme before them, as in x², so runs of them
+// become lexemes of their own
+fn split_superscripts(spans: Vec<(Range<usize>, String)>) -> Vec<(Range<usize>, String)> {
+    let mut split = Vec::<(Range<usize>, String)>::new();
+    for (range, lexeme) in spans {
+        let mut start = range.start;
+        let mut part = String::new();
+        for (i, c) in lexeme.chars().enumerate() {
+            let boundary = part.chars().last().is_some_and(|last| superscript_digit(last).is_some() != superscript_digit(c).is_some());
+            if boundary {
+                split.push((start..range.start + i, std::mem::take(&mut part)));
+                start = range.start + i;
+            }
+            part.push(c);
+        }
+        split.push((start..range.end, part));
+    }
+    split
+}
@@ -0,0 +1,487 @@
+use crate::error::CalcError;
+use crate::eval::Value;
+use crate::notation;
+use crate::parser::Expr;
+use crate::printer;
+use crate::settings::NumberFormat;
+use crate::token::{TokenType, TokenPrio};
+
+// Expression as LaTeX math, grouped the way it was parsed
+pub(crate) fn to_latex(expr: &Expr) -> String {
+    match expr {
+        Expr::Number(number) => notation::format_number(*number, &NumberFormat::default()),
+        Expr::Text(text) => format!("\\text{{{}}}", escape_text(text)),
+        Expr::Bool(value) => format!("\\text{{{value}}}"),
+        Expr::Name(name) => match name.as_str() {
+            "pi" => String::from("\\pi"),
+            "tau" => String::from("\\tau"),
+            _ if name.chars().count() == 1 => name.clone(),
+            _ => format!("\\mathrm{{{name}}}"),
+        },
+        Expr::Call(name, args) => call(name, args),
+        Expr::Negate(operand) => format!("-{}", right_operand(operand, TokenPrio::EXP)),
+        Expr::Binary(TokenType::Division, lhs, rhs) => format!("\\frac{{{}}}{{{}}}", to_latex(lhs), to_latex(rhs)),
+        Expr::Binary(TokenType::Exponent, base, exponent) => {
+            // The exponent is grouped by its braces, only the base may need parentheses
+            format!("{}^{{{}}}", right_operand(base, TokenPrio::EXP + 1), to_latex(exponent))
+        },
+        Expr::Binary(ttype, lhs, rhs) => {
+            let op_prio = prio(expr);
+            let symbol = match ttype {
+                TokenType::Addition => "+",
+                TokenType::Subtract => "-",
+                TokenType::Multiply => "\\cdot",
+                TokenType::Equal => "=",
+                TokenType::NotEqual => "\\neq",
+                TokenType::Less => "<",
+                TokenType::LessEqual => "\\leq",
+                TokenType::Greater => ">",
+                TokenType::GreaterEqual => "\\geq",
+                TokenType::Concat => "\\mathbin{\\&}",
+                _ => printer::symbol(*ttype),
+            };
+            format!("{} {symbol} {}", operand_latex(lhs, op_prio), right_operand(rhs, op_prio + 1))
+        },
+        Expr::Conditional(condition, then, otherwise) => {
+            format!(
+                "\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+                to_latex(then), to_latex(condition), to_latex(otherwise),
+            )
+        },
+    }
+}
+
+// Characters LaTeX gives a meaning to, as \text writes them literally
+const TEXT_ESCAPES: &[(char, &str)] = &[
+    ('\\', "\\textbackslash{}"),
+    ('{', "\\{"),
+    ('}', "\\}"),
+    ('$', "\\$"),
+    ('&', "\\&"),
+    ('#', "\\#"),
+    ('%', "\\%"),
+    ('_', "\\_"),
+    ('^', "\\^{}"),
+    ('~', "\\~{}"),
+];
+
+fn escape_text(text: &str) -> String {
+    text.chars()
+        .map(|c| match TEXT_ESCAPES.iter().find(|(special, _)| *special == c) {
+            Some((_, escaped)) => escaped.to_string(),
+            None => c.to_string(),
+        })
+        .collect()
+}
+
+// Result as LaTeX, with scientific notation written as a power of ten
+pub(crate) fn value_latex(value: &Value, number: &NumberFormat) -> String {
+    match value {
+        Value::Number(_) => {
+            let shown = value.display(number);
+            match shown.split_once('e') {
+                Some((mantissa, exponent)) => format!("{mantissa} \\times 10^{{{exponent}}}"),
+                None => shown,
+            }
+        },
+        _ => to_latex(&value.clone().into_expr()),
+    }
+}
+
+fn call(name: &str, args: &[Expr]) -> String {
+    let args: Vec<String> = args.iter().map(to_latex).collect();
+    match name {
+        "sqrt" => format!("\\sqrt{{{}}}", args[0]),
+        "cbrt" => format!("\\sqrt[3]{{{}}}", args[0]),
+        "abs" => format!("\\left|{}\\right|", args[0]),
+        "floor" => format!("\\left\\lfloor {} \\right\\rfloor", args[0]),
+        "ceil" => format!("\\left\\lceil {} \\right\\rceil", args[0]),
+        "exp" => format!("e^{{{}}}", args[0]),
+        "log" => format!("\\log_{{10}}\\left({}\\right)", args[0]),
+        // Functions LaTeX has its own command for
+        "ln" | "sin" | "cos" | "tan" | "min" | "max" => format!("\\{name}\\left({}\\right)", args.join(", ")),
+        "asin" | "acos" | "atan" => format!("\\arc{}\\left({}\\right)", &name[1..], args.join(", ")),
+        _ => format!("\\operatorname{{{name}}}\\left({}\\right)", args.join(", ")),
+    }
+}
+
+// Operand wrapped in \left( \right) when it binds looser than min_prio
+fn operand_latex(expr: &Expr, min_prio: i8) -> String {
+    if prio(expr) < min_prio {
+        format!("\\left({}\\right)", to_latex(expr))
+    }
+    else {
+        to_latex(expr)
+    }
+}
+
+// Operand after an operator, where a sign of its own is wrapped too, 2 - (-3)
+fn right_operand(expr: &Expr, min_prio: i8) -> String {
+    let signed = match expr {
+        Expr::Number(number) => number.is_sign_negative(),
+        Expr::Negate(_) => true,
+        _ => false,
+    };
+    if signed || prio(expr) < min_prio {
+        format!("\\left({}\\right)", to_latex(expr))
+    }
+    else {
+        to_latex(expr)
+    }
+}
+
+fn prio(expr: &Expr) -> i8 {
+    match expr {
+        // A fraction is already grouped by its bar
+        Expr::Binary(TokenType::Division, _, _) => TokenPrio::PAR,
+        // Written as a power of e, which cannot take another exponent directly
+        Expr::Call(name, _) if name == "exp" => TokenPrio::EXP,
+        _ => printer::prio(expr),
+    }
+}
+
+// Greek letters read as names, pi and tau are constants
+const GREEK: &[&str] = &[
+    "alpha", "beta", "gamma", "delta", "epsilon", "varepsilon", "zeta", "eta", "theta", "vartheta",
+    "iota", "kappa", "lambda", "mu", "nu", "xi", "pi", "rho", "sigma", "tau", "upsilon", "phi",
+    "varphi", "chi", "psi", "omega",
+];
+
+// LaTeX math rewritten as the space separated tokens of a plain expression, so
+// it is parsed into the same tree the plain syntax gives
+pub(crate) fn from_latex(input: &str) -> Result<String, CalcError> {
+    let mut lexer = Lexer { chars: input.chars().collect(), pos: 0, out: Vec::new() };
+    lexer.sequence(None)?;
+    Ok(lexer.out.join(" "))
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    out: Vec<String>,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn push(&mut self, lexeme: &str) {
+        self.out.push(lexeme.to_string());
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace() || c == '~') {
+            self.pos += 1;
+        }
+    }
+
+    // Lexemes produced by f, kept apart so they can be placed elsewhere
+    fn capture(&mut self, f: impl FnOnce(&mut Lexer) -> Result<(), CalcError>) -> Result<Vec<String>, CalcError> {
+        let out = std::mem::take(&mut self.out);
+        let result = f(self);
+        let captured = std::mem::replace(&mut self.out, out);
+        result.map(|_| captured)
+    }
+
+    // Items up to the closing character, or the end of input without one
+    fn sequence(&mut self, close: Option<char>) -> Result<(), CalcError> {
+        loop {
+            self.skip_spaces();
+            match (self.peek(), close) {
+                (None, None) => return Ok(()),
+                (None, Some(close)) => return Err(CalcError::new(format!("Expected '{close}' before end of expression"))),
+                (Some(c), Some(close)) if c == close => {
+                    self.pos += 1;
+                    return Ok(());
+                },
+                (Some('}'), _) => return Err(CalcError::new("Unmatched '}'")),
+                _ => self.item()?,
+            }
+        }
+    }
+
+    // Argument of a command such as \frac, a braced group or a single character
+    // or command, always wrapped in parentheses
+    fn argument(&mut self) -> Result<(), CalcError> {
+        self.skip_spaces();
+        self.push("(");
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                self.sequence(Some('}'))?;
+            },
+            // \frac12 is 1/2, a bare argument is a single digit
+            Some(c) if c.is_ascii_digit() => {
+                self.pos += 1;
+                self.out.push(c.to_string());
+            },
+            Some(_) => self.item()?,
+            None => return Err(CalcError::new("Expected an argument before end of expression")),
+        }
+        self.push(")");
+        Ok(())
+    }
+
+    fn item(&mut self) -> Result<(), CalcError> {
+        let Some(c) = self.peek() else {
+            return Err(CalcError::new("Unexpected end of expression"));
+        };
+        self.pos += 1;
+
+        match c {
+            '0'..='9' | '.' => {
+                let mut number = c.to_string();
+                while let Some(next) = self.peek().filter(|next| next.is_ascii_digit() || *next == '.') {
+                    number.push(next);
+                    self.pos += 1;
+                }
+                self.out.push(number);
+            },
+            // Every letter is a name of its own, xy is x times y
+            c if c.is_ascii_alphabetic() => self.out.push(c.to_string()),
+            '+' | '-' | '*' | '/' | '(' | ')' | '[' | ']' | '|' | ',' | '<' | '>' => self.out.push(c.to_string()),
+            '=' => self.push("=="),
+            '{' => {
+                self.push("(");
+                self.sequence(Some('}'))?;
+                self.push(")");
+            },
+            '^' => {
+                self.push("^");
+                self.argument()?;
+            },
+            '\\' => self.command()?,
+            _ => return Err(CalcError::new(format!("Unexpected character in LaTeX: {c}"))),
+        }
+
+        Ok(())
+    }
+
+    fn command(&mut self) -> Result<(), CalcError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        // Commands are a run of letters or a single other character such as \{
+        if self.pos == start && self.peek().is_some() {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+
+        match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                self.push("(");
+                self.argument()?;
+                self.push("/");
+                self.argument()?;
+                self.push(")");
+            },
+            "sqrt" => {
+                self.skip_spaces();
+                if self.peek() == Some('[') {
+                    // The n-th root is the power 1/n, written after the radicand
+                    self.pos += 1;
+                    let index = self.capture(|lexer| lexer.sequence(Some(']')))?;
+                    self.push("(");
+                    self.argument()?;
+                    self.push("^");
+                    self.push("(");
+                    self.push("1");
+                    self.push("/");
+                    self.push("(");
+                    self.out.extend(index);
+                    self.push(")");
+                    self.push(")");
+                    self.push(")");
+                }
+                else {
+                    self.push("sqrt");
+                    self.argument()?;
+                }
+            },
+            "cdot" | "times" | "ast" => self.push("*"),
+            "div" => self.push("/"),
+            "le" | "leq" => self.push("<="),
+            "ge" | "geq" => self.push(">="),
+            "ne" | "neq" => self.push("!="),
+            "lt" => self.push("<"),
+            "gt" => self.push(">"),
+            // Sizing only, the delimiter after them is read as usual
+            "left" | "right" | "big" | "Big" | "bigg" | "Bigg" => {
+                self.skip_spaces();
+                // \left. and \right. stand for no delimiter at all
+                if self.peek() == Some('.') {
+                    self.pos += 1;
+                }
+            },
+            "&" => self.push("&"),
+            // Spacing around the operator only, \mathbin{\&} is &
+            "mathbin" | "mathrel" => {
+                self.skip_spaces();
+                if self.peek() != Some('{') {
+                    return Err(CalcError::new(format!("Expected '{{' after \\{name}")));
+                }
+                self.pos += 1;
+                self.sequence(Some('}'))?;
+            },
+            "begin" => self.cases()?,
+            "{" => self.push("{"),
+            "}" => self.push("}"),
+            "vert" | "lvert" | "rvert" => self.push("|"),
+            "lfloor" => self.push("⌊"),
+            "rfloor" => self.push("⌋"),
+            "lceil" => self.push("⌈"),
+            "rceil" => self.push("⌉"),
+            // Spacing
+            "," | ";" | ":" | "!" | " " | "quad" | "qquad" => {},
+            "sin" | "cos" | "tan" | "ln" | "exp" | "min" | "max" => self.function(&name)?,
+            "arcsin" | "arccos" | "arctan" => self.function(&format!("a{}", &name[3..]))?,
+            "log" => {
+                // Only common logarithms, \log_{10} or plain \log
+                self.skip_spaces();
+                if self.peek() == Some('_') {
+                    self.pos += 1;
+                    let base = self.capture(|lexer| lexer.argument())?;
+                    if base != ["(", "10", ")"] {
+                        return Err(CalcError::new("Only base 10 logarithms are supported, use \\ln for natural ones"));
+                    }
+                }
+                self.function("log")?;
+            },
+            "operatorname" | "mathrm" | "text" | "mathit" => {
+                let word = if name == "text" { self.braced_text()? } else { self.braced_word()? };
+                if name == "operatorname" {
+                    self.function(&word)?;
+                }
+                // Text values are written with \text
+                else if name == "text" {
+                    self.out.push(format!("\"{word}\""));
+                }
+                else {
+                    self.out.push(word);
+                }
+            },
+            _ if GREEK.contains(&name.as_str()) => self.out.push(name),
+            _ => return Err(CalcError::new(format!("Unknown LaTeX command: \\{name}"))),
+        }
+
+        Ok(())
+    }
+
+    // value & \text{if } condition \\ other & \text{otherwise} \end{cases}, after
+    // \begin, as written for conditionals. Other environments and rows are not read
+    fn cases(&mut self) -> Result<(), CalcError> {
+        let environment = self.braced_word()?;
+        if environment != "cases" {
+            return Err(CalcError::new(format!("Unknown LaTeX environment: {environment}")));
+        }
+
+        let then = self.capture(|lexer| lexer.until("&"))?;
+        self.pos += 1;
+        let mut condition = self.capture(|lexer| lexer.until("\\\\"))?;
+        self.pos += 2;
+        if condition.first().is_some_and(|word| word == "\"if\"") {
+            condition.remove(0);
+        }
+        let otherwise = self.capture(|lexer| lexer.until("&"))?;
+        self.pos += 1;
+        let last = self.capture(|lexer| lexer.until("\\end"))?;
+        self.pos += 4;
+        if last != ["\"otherwise\""] || self.braced_word()? != "cases" {
+            return Err(CalcError::new("Only two cases are read, the second one \\text{otherwise}"));
+        }
+
+        // (condition) ? (then) : (otherwise)
+        for (part, lexemes) in [("", condition), ("?", then), (":", otherwise)] {
+            if !part.is_empty() {
+                self.push(part);
+            }
+            self.push("(");
+            self.out.extend(lexemes);
+            self.push(")");
+        }
+        Ok(())
+    }
+
+    // Items up to the text end, which is left unread
+    fn until(&mut self, end: &str) -> Result<(), CalcError> {
+        loop {
+            self.skip_spaces();
+            let rest = &self.chars[self.pos..];
+            if rest.iter().take(end.chars().count()).copied().eq(end.chars()) {
+                return Ok(());
+            }
+            match self.peek() {
+                None => return Err(CalcError::new(format!("Expected '{end}' before end of expression"))),
+                Some('}') => return Err(CalcError::new("Unmatched '}'")),
+                _ => self.item()?,
+            }
+        }
+    }
+
+    // Function name followed by its parenthesized argument, \sin x is sin(x)
+    fn function(&mut self, name: &str) -> Result<(), CalcError> {
+        self.push(name);
+        self.skip_spaces();
+        if self.starts_with_paren() {
+            return Ok(());
+        }
+        self.argument()
+    }
+
+    // Whether a parenthesis, possibly after \left, comes next
+    fn starts_with_paren(&self) -> bool {
+        let rest: String = self.chars[self.pos..].iter().take(6).collect();
+        rest.starts_with('(') || rest.starts_with("\\left(")
+    }
+
+    // Plain text between braces, as in \mathrm{abc}
+    fn braced_word(&mut self) -> Result<String, CalcError> {
+        self.skip_spaces();
+        if self.peek() != Some('{') {
+            return Err(CalcError::new("Expected '{' after a text command"));
+        }
+        self.pos += 1;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c != '}') {
+            self.pos += 1;
+        }
+        if self.peek().is_none() {
+            return Err(CalcError::new("Expected '}' before end of expression"));
+        }
+        let word: String = self.chars[start..self.pos].iter().collect::<String>().trim().to_string();
+        self.pos += 1;
+        Ok(word)
+    }
+
+    // Braced argument of \text, with the escapes of special characters undone
+    fn braced_text(&mut self) -> Result<String, CalcError> {
+        self.skip_spaces();
+        if self.peek() != Some('{') {
+            return Err(CalcError::new("Expected '{' after a text command"));
+        }
+        self.pos += 1;
+        let mut text = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(CalcError::new("Expected '}' before end of expression")),
+                Some('}') => break,
+                Some('\\') => {
+                    let rest = &self.chars[self.pos..];
+                    let (special, escaped) = TEXT_ESCAPES.iter()
+                        .find(|(_, escaped)| rest.iter().take(escaped.chars().count()).copied().eq(escaped.chars()))
+                        .ok_or_else(|| CalcError::new("Unknown escape in \\text"))?;
+                    text.push(*special);
+                    self.pos += escaped.chars().count();
+                },
+                Some(c) => {
+                    text.push(c);
+                    self.pos += 1;
+                },
+            }
+        }
+        self.pos += 1;
+        Ok(text.trim().to_string())
+    }
+}
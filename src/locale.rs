@@ -0,0 +1,110 @@
+use crate::error::CalcError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Locale {
+    pub decimal: char,
+    // Separator between groups of three digits in results
+    pub group: Option<char>,
+}
+
+impl Default for Locale {
+    fn default() -> Locale {
+        Locale { decimal: '.', group: None }
+    }
+}
+
+const NAMES: [&str; 5] = ["c", "en", "ch", "de", "fr"];
+
+impl Locale {
+    pub fn from_name(name: &str) -> Result<Locale, CalcError> {
+        match name {
+            "c" => Ok(Locale { decimal: '.', group: None }),
+            "en" => Ok(Locale { decimal: '.', group: Some(',') }),
+            "ch" => Ok(Locale { decimal: '.', group: Some('\'') }),
+            "de" => Ok(Locale { decimal: ',', group: Some('.') }),
+            "fr" => Ok(Locale { decimal: ',', group: Some(' ') }),
+            _ => Err(CalcError::new(format!("Unknown locale: {name} (expected c, en, ch, de or fr)"))),
+        }
+    }
+
+    // Name the locale was chosen by
+    pub fn name(&self) -> &'static str {
+        NAMES.into_iter()
+            .find(|name| Locale::from_name(name).is_ok_and(|locale| locale == *self))
+            .unwrap_or("c")
+    }
+
+    fn is_input_group(&self, c: char) -> bool {
+        // A comma group would clash with argument separators
+        if self.group == Some(c) && c != ',' {
+            return true;
+        }
+        self.decimal == ',' && matches!(c, '.' | ' ' | '\u{a0}' | '\u{202f}')
+    }
+
+    // Rewrites numbers as written in this locale into plain input. With a decimal
+    // comma, function arguments are separated by ';' instead of ','
+    pub fn delocalize(&self, input: &str) -> String {
+        let chars: Vec<char> = input.chars().collect();
+        let mut output = String::with_capacity(input.len());
+        let mut in_quotes = false;
+
+        for i in 0..chars.len() {
+            let cur = chars[i];
+            let after_digit = i > 0 && chars[i - 1].is_ascii_digit();
+
+            if cur == '"' {
+                in_quotes = !in_quotes;
+            }
+            if in_quotes {
+                output.push(cur);
+                continue;
+            }
+
+            // Group separators sit between a digit and exactly three more digits
+            if after_digit && self.is_input_group(cur) && is_digit_group(&chars[i + 1..]) {
+                continue;
+            }
+
+            if self.decimal == ',' {
+                match cur {
+                    ',' if after_digit && chars.get(i + 1).is_some_and(char::is_ascii_digit) => output.push('.'),
+                    ';' => output.push(','),
+                    _ => output.push(cur),
+                }
+            }
+            else {
+                output.push(cur);
+            }
+        }
+
+        output
+    }
+
+    // Swaps the decimal point and groups the integer digits of a formatted number
+    pub fn localize(&self, number: &str) -> String {
+        let (sign, unsigned) = match number.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", number),
+        };
+        let int_len = unsigned.find(|c: char| !c.is_ascii_digit()).unwrap_or(unsigned.len());
+        let (int_digits, rest) = unsigned.split_at(int_len);
+
+        let mut output = String::from(sign);
+        for (i, digit) in int_digits.chars().enumerate() {
+            if let Some(group) = self.group && i > 0 && (int_len - i) % 3 == 0 {
+                output.push(group);
+            }
+            output.push(digit);
+        }
+        output.extend(rest.chars().map(|c| if c == '.' { self.decimal } else { c }));
+
+        output
+    }
+}
+
+fn is_digit_group(chars: &[char]) -> bool {
+    chars.len() >= 3 &&
+        chars[..3].iter().all(char::is_ascii_digit) &&
+        !chars.get(3).is_some_and(char::is_ascii_digit)
+}
@@ -7,234 +7,565 @@
  *  op_div(a: f64, b: f64) -> f64;
  *  
  *  Input:
- *  Math expression
+ *  Math expression, either from the command line arguments, one per line from
+ *  files or piped stdin, or entered in an interactive session where
+ *  name = expression defines a value that follows changes to the names it uses
+ *  A minus sign directly before digits belongs to the number, so -2^2 = 4, while
+ *  before anything else it negates the power, so -x^2 = -4 when x = 2.
+ *  Juxtaposed operands multiply (2pi, 3(4 + 5), (a + b)(a - b)). By default this
+ *  binds like '*' so 6/2(1+2) = 9, with --implicit tight it binds tighter than
+ *  '*' and '/' so 6/2(1+2) = 1. A name followed by '(' is always a function call.
+ *  Unicode symbols such as ×, ÷, −, π, √x and superscript exponents (x²) are
+ *  read as their plain equivalents.
+ *  Groups may use (), [] or {} as long as each is closed by its own kind.
+ *  |x| is the absolute value, ⌊x⌋ and ⌈x⌉ round down and up.
+ *  With --locale de or fr numbers use a decimal comma (1.234,56 or 1 234,56)
+ *  and function arguments are separated by ';'.
+ *  With --syntax latex, LaTeX math such as \frac{1}{2} + \sqrt{3} \cdot \pi is read
+ *  into the same expression tree.
+ *  With --syntax sheet, spreadsheet formulas such as =SUM(A1:A3)*2 are read with
+ *  cells taken from the CSV file given with --grid.
+ *  Defaults for the options, and a prelude of definitions, can be kept in
+ *  calculator/config.toml under ~/.config.
  *
  *  Logic:
  *  1. Input
  *  2. Token Parse
- *  3. BEDMAS operations
- *  4. Output answer
+ *  3. Build expression tree
+ *  4. Evaluate tree
+ *  5. Output answer
  */
 
-use std::io::{self, Write};
+use std::borrow::Cow;
+use std::io::{self, IsTerminal};
+use std::ops::Range;
 
+mod batch;
+mod cli;
+mod completion;
+mod config;
+mod convert;
+mod editor;
+mod error;
+mod eval;
+mod explain;
+mod formatter;
+mod functions;
+mod highlight;
+mod latex;
+mod locale;
+mod notation;
+mod output;
+mod parser;
+mod printer;
+mod repl;
+mod rpn;
+mod settings;
+mod sheet;
+mod terminal;
 mod token;
-use token::{Token, TokenType, TokenPrio};
+use error::CalcError;
+use eval::Value;
+use parser::Expr;
+use settings::{OutputFormat, Settings, Syntax};
+use token::{Token, TokenType, TokenPrio, split_formatted};
 
 fn main() {
-    println!("(Negative numbers should be enclosed with brackets for proper formatting)");
-    print!("Enter a math expression: ");
-    io::stdout()
-        .flush()
-        .unwrap();
-
-    // Input
-    let mut user_input = String::new();
-    io::stdin()
-        .read_line(&mut user_input)
-        .expect("Failed to get input");
-
-    // Token parsing
-    let formatted = format_tokens(&user_input);
-    let tokens = parse_tokens(&formatted);
-
-    // BEDMAS
-    let value = calculate(tokens);
-
-    // Output
-    println!("{formatted} = {value}");
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("ERROR: {error}");
+            std::process::exit(2);
+        },
+    };
+    let args = match cli::parse_args(std::env::args().skip(1), config.settings) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("ERROR: {error}");
+            eprint!("{}", cli::USAGE);
+            std::process::exit(2);
+        },
+    };
+
+    if args.help {
+        print!("{}", cli::USAGE);
+        return;
+    }
+
+    // Subcommands that rewrite expressions or evaluate whole sheets
+    let rewritten = match args.command {
+        cli::Command::Evaluate => None,
+        cli::Command::Format => {
+            let mut settings = args.settings.clone();
+            Some(run_rewrite(&args, |input| formatter::format_expression(input, &mut settings, &args.style)))
+        },
+        cli::Command::Convert(to) => Some(run_rewrite(&args, |input| convert::convert(input, &args.settings, to))),
+        cli::Command::Sheet => Some(sheet::run_sheet(args.csv.as_deref().unwrap_or("-"), &args.settings.number)),
+    };
+    if let Some(succeeded) = rewritten {
+        if !succeeded {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Definitions of the prelude are known to everything evaluated from here on
+    let mut session = repl::Session::new(args.settings.clone());
+    if let Some(prelude) = &config.prelude && !batch::run_prelude(prelude, &mut session) {
+        std::process::exit(1);
+    }
+
+    // Expressions passed as arguments print only the result
+    if let Some(expression) = &args.expression {
+        let settings = &session.settings;
+        if args.explain || args.latex {
+            let printed = if args.explain { print_explained(expression, settings) } else { print_latex(expression, settings) };
+            if !printed {
+                std::process::exit(1);
+            }
+            return;
+        }
+        let (formatted, result) = evaluate_input(expression, settings);
+        match (&result, settings.format) {
+            (Ok(value), OutputFormat::Plain) => println!("{}", value.display(&settings.number)),
+            (Err(error), OutputFormat::Plain) => eprint!("{}", error_report(&formatted, error)),
+            (_, format) => {
+                if let Some(header) = output::header(format) {
+                    println!("{header}");
+                }
+                let record = output::Record { input: expression, expression: &formatted, result: &result, number: &settings.number };
+                println!("{}", output::render(format, &record));
+            },
+        }
+        if result.is_err() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if args.rpn {
+        rpn::run_rpn(&args.settings);
+        return;
+    }
+
+    // Files, or stdin when it is piped in, are evaluated line by line
+    let mut files = args.files.clone();
+    if files.is_empty() && !io::stdin().is_terminal() {
+        files.push(String::from("-"));
+    }
+    if !files.is_empty() {
+        if !batch::run_batch(&files, &mut session, args.keep_going) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    repl::run(&args, session);
 }
 
-fn format_tokens(input: &String) -> String {
-    // Get true string state with no spaces between items
-    let input: String = input.trim()
-        .split_whitespace()
-        .collect::<Vec<&str>>()
-        .join("");
-    
-    // Add in proper spaces between items
-    return add_whitespace(input).trim().to_string();
+// Rewrites the expression argument, otherwise the given files or stdin
+fn run_rewrite(args: &cli::Args, mut rewrite: impl FnMut(&str) -> (String, Result<String, CalcError>)) -> bool {
+    let Some(expression) = &args.expression else {
+        let files = if args.files.is_empty() { vec![String::from("-")] } else { args.files.clone() };
+        return formatter::rewrite_files(&files, &mut rewrite, args.check);
+    };
+
+    let (formatted, result) = rewrite(expression);
+    match result {
+        Ok(rewritten) if args.check => {
+            if rewritten != *expression {
+                eprintln!("Not formatted, expected: {rewritten}");
+                return false;
+            }
+            true
+        },
+        Ok(rewritten) => {
+            println!("{rewritten}");
+            true
+        },
+        Err(error) => {
+            eprint!("{}", error_report(&formatted, &error));
+            false
+        },
+    }
+}
+
+fn evaluate_input(input: &str, settings: &Settings) -> (String, Result<Value, CalcError>) {
+    let (formatted, expr) = parse_input(input, settings);
+    let result = expr.and_then(|expr| evaluate_expr(&expr, settings));
+
+    (formatted, result)
+}
+
+// Expression tree of the input, and its space separated form in plain syntax
+fn parse_input(input: &str, settings: &Settings) -> (String, Result<Expr, CalcError>) {
+    let formatted = match settings.syntax {
+        // Numbers written for the locale become plain numbers first
+        Syntax::Infix => Ok(format_tokens(&settings.number.locale.delocalize(input))),
+        // Signs glue onto numbers the way they do in plain syntax, so -2^2 agrees
+        Syntax::Latex => latex::from_latex(input).map(|plain| format_tokens(&plain)),
+        // Read into a tree of their own, which is kept. The plain form is only shown
+        Syntax::Rpn => return parsed_tree(input, rpn::parse_rpn(input)),
+        Syntax::Sexpr => return parsed_tree(input, convert::parse_sexpr(input)),
+        Syntax::Sheet => return parsed_tree(input, sheet::parse_formula(input)),
+    };
+    let formatted = match formatted {
+        Ok(formatted) => formatted,
+        Err(error) => return (input.trim().to_string(), Err(error)),
+    };
+    let expr = parse_tokens(&formatted).and_then(|tokens| parser::parse(&tokens, settings));
+
+    (formatted, expr)
+}
+
+fn parsed_tree(input: &str, expr: Result<Expr, CalcError>) -> (String, Result<Expr, CalcError>) {
+    match expr {
+        Ok(expr) => (format_tokens(&printer::print(&expr)), Ok(expr)),
+        Err(error) => (input.trim().to_string(), Err(error)),
+    }
+}
+
+// Prints each evaluation step, then the result, returns whether it succeeded
+fn print_explained(input: &str, settings: &Settings) -> bool {
+    let (formatted, expr) = parse_input(input, settings);
+    let expr = match expr {
+        Ok(expr) => expr,
+        Err(error) => {
+            eprint!("{}", error_report(&formatted, &error));
+            return false;
+        },
+    };
+
+    let expr = functions::expand(&expr, &settings.functions);
+    let (steps, result) = explain::explain(&expr, &scope_of(&expr, settings), settings.angle);
+    print!("{}", explain::render(&printer::print(&expr), &steps));
+    match result {
+        Ok(value) => {
+            println!("= {}", value.display(&settings.number));
+            true
+        },
+        Err(error) => {
+            eprintln!("ERROR: {error}");
+            false
+        },
+    }
+}
+
+// Prints the expression and its result as LaTeX, returns whether it succeeded
+fn print_latex(input: &str, settings: &Settings) -> bool {
+    let (formatted, expr) = parse_input(input, settings);
+    let result = expr.and_then(|expr| {
+        let value = evaluate_expr(&expr, settings)?;
+        Ok(format!("{} = {}", latex::to_latex(&expr), latex::value_latex(&value, &settings.number)))
+    });
+
+    match result {
+        Ok(latex) => {
+            println!("{latex}");
+            true
+        },
+        Err(error) => {
+            eprint!("{}", error_report(&formatted, &error));
+            false
+        },
+    }
+}
+
+fn error_report(formatted: &str, error: &CalcError) -> String {
+    format!("{}ERROR: {error}\n", error_pointer(formatted, error))
+}
+
+fn error_pointer(formatted: &str, error: &CalcError) -> String {
+    // Point at the offending token when there is one
+    match error.column(formatted) {
+        Some(column) => format!("{formatted}\n{}^\n", " ".repeat(column)),
+        None => String::new(),
+    }
 }
 
-fn add_whitespace(input: String) -> String {
-    // Each char in string, assuming ASCII
-    let mut output = String::with_capacity(input.len() * 2);
+fn format_tokens(input: &str) -> String {
+    // Swap unicode math symbols for their plain equivalents
+    let input = replace_unicode(input);
+
+    // Add in proper spaces between items
+    split_lexemes(input.trim()).join(" ")
+}
 
-    // Tracker for adding special spacing between numbers and other items
-    let mut prev_num = false;
-    let mut prev_open_paren = true;
+fn replace_unicode(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
 
-    for i in 0..input.len() {
-        // Char value
-        let cur = input.as_bytes()[i];
-        let cur = cur as char;
+    // Tracker for joining runs of superscripts into a single exponent
+    let mut in_superscript = false;
+    // Quoted text is left exactly as written
+    let mut in_quotes = false;
 
-        // Don't add space when numeric
-        if cur.is_numeric() {
+    for cur in input.chars() {
+        if cur == '"' {
+            in_quotes = !in_quotes;
+        }
+        if in_quotes || cur == '"' {
             output.push(cur);
-            prev_num = true;
+            in_superscript = false;
             continue;
         }
-        
-        // Add space to account for previous numeric item
-        if prev_num {
-            output.push(' ');
-        }
-        
-        // Regular logic to add space after operator
-        output.push(cur);
-        
-        // Negative sign near paren should put both together
-        if !(prev_open_paren && 
-            cur == '-') {
-            output.push(' ');
-        }
 
-        // Check for other variables for setting up next iteration
-        if cur == '(' {
-            prev_open_paren = true;
+        // Superscript digits become a regular exponent, x² is x^2
+        if let Some(digit) = superscript_digit(cur) {
+            if !in_superscript {
+                output.push('^');
+            }
+            output.push(digit);
+            in_superscript = true;
+            continue;
         }
-        else {
-            prev_open_paren = false;
+        in_superscript = false;
+
+        match cur {
+            '×' | '·' | '⋅' | '∙' => output.push('*'),
+            '÷' | '∕' => output.push('/'),
+            '−' | '–' => output.push('-'),
+            '≠' => output.push_str("!="),
+            '≤' => output.push_str("<="),
+            '≥' => output.push_str(">="),
+            // Spaced so that 2πr does not become a single name
+            'π' => output.push_str(" pi "),
+            'τ' => output.push_str(" tau "),
+            _ => output.push(cur),
         }
-        prev_num = false;
     }
 
-    return output;
+    output
 }
 
-fn parse_tokens(input: &String) -> Vec<Token> {
-    // Split tokens
-    let parts = input.split(" ");
-    
-    // Loop through each token to add to vector
-    let mut tokens = Vec::<Token>::new();
-    for part in parts {
-        // Attempt to parse number
-        match part.parse::<f64>() {
-            Ok(_) => {
-                tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Number), prio: (TokenPrio::NONE) });
-                continue;
-            },
-            // Not a number
-            Err(_) => {
-                // Do nothing
-            },
-        }
-        
-        // Setup different tokens
-        
-        // Part length should ALWAYS be 1 with operators
-        if part.len() != 1 {
-            // Most likely end of parts
-            break;
-        }
-        
-        match part {
-            "+" => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Addition), prio: (TokenPrio::ADD) }),
-            "-" => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Subtract), prio: (TokenPrio::SUB) }),
-            "*" => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Multiply), prio: (TokenPrio::MUL) }),
-            "/" => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Division), prio: (TokenPrio::DIV) }),
-            "^" => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Exponent), prio: (TokenPrio::EXP) }),
-            "(" => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::ParenOpen), prio: (TokenPrio::PAR) }),
-            ")" => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::ParenClose), prio: (TokenPrio::PAR) }),
-            _ => println!("Unknown operator: {part}"),
-        }
+fn superscript_digit(c: char) -> Option<char> {
+    match c {
+        '⁰' => Some('0'),
+        '¹' => Some('1'),
+        '²' => Some('2'),
+        '³' => Some('3'),
+        '⁴' => Some('4'),
+        '⁵' => Some('5'),
+        '⁶' => Some('6'),
+        '⁷' => Some('7'),
+        '⁸' => Some('8'),
+        '⁹' => Some('9'),
+        '⁻' => Some('-'),
+        _ => None,
     }
-    
-    return tokens;
 }
 
-fn calculate(tokens: Vec<Token>) -> f64 {
-    let mut tokens = tokens;
+fn split_lexemes(input: &str) -> Vec<String> {
+    split_lexeme_spans(input).into_iter()
+        .map(|(_, lexeme)| lexeme)
+        .collect()
+}
+
+// Each lexeme with the range of characters of the input it was read from
+fn split_lexeme_spans(input: &str) -> Vec<(Range<usize>, String)> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut lexemes = Vec::<(Range<usize>, String)>::new();
+
+    // Tracker for whether a minus sign would be a negative sign rather than a subtraction
+    let mut prefix_position = true;
 
-    // Loop through tokens based on prio
-    for cur_prio in (0..=TokenPrio::MAX).rev() {
-        for i in 0..tokens.len() {
-            if i >= tokens.len() {
-                break;
+    let mut i = 0;
+    while i < chars.len() {
+        let cur = chars[i];
+        let start = i;
+
+        // Whitespace only separates items
+        if cur.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // Negative sign near paren or operator should be put together with the number
+        let negative = cur == '-' && prefix_position && next_visible(&chars, i + 1).is_some_and(is_number_char);
+
+        // Numbers
+        if is_number_char(cur) || negative {
+            let mut number = String::from(cur);
+            i += 1;
+
+            // Spaces between a negative sign and its digits are dropped
+            while negative && chars[i].is_whitespace() {
+                i += 1;
             }
+            while i < chars.len() && is_number_char(chars[i]) {
+                number.push(chars[i]);
+                i += 1;
+            }
+            // An exponent belongs to the number, as in 1.2345e3 or 5e-7
+            let exponent = exponent_length(&chars[i..]);
+            number.extend(&chars[i..i + exponent]);
+            i += exponent;
+            lexemes.push((start..i, number));
+            prefix_position = false;
+            continue;
+        }
 
-            let token = &tokens[i];
-            if cur_prio == token.prio {
-                // Manage brackets
-                if token.ttype == TokenType::ParenOpen {
-                    calculate_brackets(&mut tokens, i);
-                    continue;
+        // Quoted text, spaces included, up to the closing quote
+        if cur == '"' {
+            let mut text = String::from(cur);
+            i += 1;
+            while i < chars.len() {
+                text.push(chars[i]);
+                i += 1;
+                if chars[i - 1] == '"' {
+                    break;
                 }
+            }
+            lexemes.push((start..i, text));
+            prefix_position = false;
+            continue;
+        }
 
-                // Manage other
-                
-                // Cannot have an operand as first or last token
-                if i == 0 || i == tokens.len() - 1 {
-                    continue;
-                }
+        // Words
+        if cur.is_alphabetic() {
+            let mut word = String::new();
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                word.push(chars[i]);
+                i += 1;
+            }
+            prefix_position = matches!(word.as_str(), "if" | "then" | "else");
+            lexemes.push((start..i, word));
+            continue;
+        }
 
-                // Parse values first
-                let a: f64 = tokens[i - 1].value.parse::<f64>().expect("ERROR: Could not parse f64");
-                let b: f64 = tokens[i + 1].value.parse::<f64>().expect("ERROR: Could not parse f64");
-
-                // Match operation
-                match token.ttype {
-                    TokenType::Addition => swap_expression(&mut tokens, i - 1, i + 1, op_add(a, b)),
-                    TokenType::Subtract => swap_expression(&mut tokens, i - 1, i + 1, op_sub(a, b)),
-                    TokenType::Multiply => swap_expression(&mut tokens, i - 1, i + 1, op_mul(a, b)),
-                    TokenType::Division => swap_expression(&mut tokens, i - 1, i + 1, op_div(a, b)),
-                    TokenType::Exponent => swap_expression(&mut tokens, i - 1, i + 1, op_exp(a, b)),
-                    _ => println!("Unknown token type: {:?}", token.ttype),
-                }
+        // Two character comparison operators
+        if let Some(&next) = chars.get(i + 1) {
+            let pair: String = [cur, next].iter().collect();
+            if matches!(pair.as_str(), "==" | "!=" | "<=" | ">=") {
+                lexemes.push((start..i + 2, pair));
+                prefix_position = true;
+                i += 2;
+                continue;
             }
         }
+
+        // Regular single character operator
+        lexemes.push((start..i + 1, cur.to_string()));
+        // A bar may close an absolute value, so it never starts a negative number
+        prefix_position = !matches!(cur, ')' | ']' | '}' | '|' | '⌋' | '⌉');
+        i += 1;
     }
 
-    match tokens[0].value.parse::<f64>() {
-        Ok(number) => number,
-        Err(_) => {
-            println!("ERROR: Could not parse f64");
-            0.
-        }
+    lexemes
+}
+
+fn is_number_char(c: char) -> bool {
+    c.is_ascii_digit() || c == '.'
+}
+
+// Length of an exponent such as e3, E+3 or e-7 at the start of chars, 0 if
+// there is none. A bare e stays the constant, so 2e is 2 * e
+fn exponent_length(chars: &[char]) -> usize {
+    if !matches!(chars.first(), Some('e' | 'E')) {
+        return 0;
     }
+    let sign = usize::from(matches!(chars.get(1), Some('+' | '-')));
+    let digits = chars[1 + sign..].iter().take_while(|c| c.is_ascii_digit()).count();
+    if digits == 0 { 0 } else { 1 + sign + digits }
+}
+
+fn next_visible(chars: &[char], begin: usize) -> Option<char> {
+    chars[begin.min(chars.len())..].iter()
+        .copied()
+        .find(|c| !c.is_whitespace())
 }
 
-fn calculate_brackets(tokens: &mut Vec<Token>, begin: usize) {
-    // Find end of bracket position
-    let mut end = tokens.len();
-    for i in begin + 1..tokens.len() {
-        let token = &tokens[i];
-        if token.ttype == TokenType::ParenOpen {
-            // Recursive call to handle sub parentheses
-            calculate_brackets(tokens, i);
+fn parse_tokens(input: &str) -> Result<Vec<Token>, CalcError> {
+    // Split tokens
+    let parts = split_formatted(input);
+    
+    // Loop through each token to add to vector
+    let mut tokens = Vec::<Token>::new();
+    for (position, part) in parts.into_iter().enumerate() {
+        // Attempt to parse number, words such as "inf" are not numbers here
+        if part.starts_with(|c: char| c == '-' || is_number_char(c)) && part.parse::<f64>().is_ok() {
+            tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Number), prio: (TokenPrio::NONE) });
+            continue;
         }
-        else if token.ttype == TokenType::ParenClose {
-            end = i;
-            break;
+        
+        // Setup different tokens
+        match part {
+            "+"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Addition),     prio: (TokenPrio::ADD)  }),
+            "-"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Subtract),     prio: (TokenPrio::SUB)  }),
+            "*"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Multiply),     prio: (TokenPrio::MUL)  }),
+            "/"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Division),     prio: (TokenPrio::DIV)  }),
+            "^"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Exponent),     prio: (TokenPrio::EXP)  }),
+            "("     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::ParenOpen),    prio: (TokenPrio::PAR)  }),
+            ")"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::ParenClose),   prio: (TokenPrio::PAR)  }),
+            "["     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::BracketOpen),  prio: (TokenPrio::PAR)  }),
+            "]"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::BracketClose), prio: (TokenPrio::PAR)  }),
+            "{"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::BraceOpen),    prio: (TokenPrio::PAR)  }),
+            "}"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::BraceClose),   prio: (TokenPrio::PAR)  }),
+            "|"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Bar),          prio: (TokenPrio::PAR)  }),
+            "⌊"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::FloorOpen),    prio: (TokenPrio::PAR)  }),
+            "⌋"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::FloorClose),   prio: (TokenPrio::PAR)  }),
+            "⌈"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::CeilOpen),     prio: (TokenPrio::PAR)  }),
+            "⌉"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::CeilClose),    prio: (TokenPrio::PAR)  }),
+            "=="    => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Equal),        prio: (TokenPrio::CMP)  }),
+            "!="    => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::NotEqual),     prio: (TokenPrio::CMP)  }),
+            "<"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Less),         prio: (TokenPrio::CMP)  }),
+            "<="    => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::LessEqual),    prio: (TokenPrio::CMP)  }),
+            ">"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Greater),      prio: (TokenPrio::CMP)  }),
+            ">="    => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::GreaterEqual), prio: (TokenPrio::CMP)  }),
+            "&"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Concat),       prio: (TokenPrio::CAT)  }),
+            "?"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Question),     prio: (TokenPrio::COND) }),
+            ":"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Colon),        prio: (TokenPrio::COND) }),
+            "if"    => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::If),           prio: (TokenPrio::COND) }),
+            "then"  => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Then),         prio: (TokenPrio::COND) }),
+            "else"  => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Else),         prio: (TokenPrio::COND) }),
+            "√"     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Root),         prio: (TokenPrio::NONE) }),
+            ","     => tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Comma),        prio: (TokenPrio::NONE) }),
+            // Quoted text
+            _ if part.starts_with('"') => {
+                if part.len() < 2 || !part.ends_with('"') {
+                    return Err(CalcError::at(position, "Unterminated text, expected a closing '\"'"));
+                }
+                tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Text), prio: (TokenPrio::NONE) });
+            },
+            // Names of constants and functions
+            _ if part.starts_with(char::is_alphabetic) => {
+                tokens.push(Token { value: (part.to_string()), ttype: (TokenType::Identifier), prio: (TokenPrio::NONE) });
+            },
+            // Nothing entered
+            ""      => continue,
+            _ => return Err(CalcError::at(position, format!("Unknown operator: {part}"))),
         }
     }
     
-    // Calculate items between these indexes
-    let sub_vec: Vec<Token> = tokens[begin+1..end].to_vec();
-    let sub_val = calculate(sub_vec);
+    Ok(tokens)
+}
 
-    // Calculate items between these indexes
-    swap_expression(tokens, begin, end, sub_val);
+// Value of the expression, with calls of functions defined in the session
+// replaced by their bodies first
+fn evaluate_expr(expr: &Expr, settings: &Settings) -> Result<Value, CalcError> {
+    let expr = functions::expand(expr, &settings.functions);
+    eval::evaluate(&expr, &scope_of(&expr, settings), settings.angle)
 }
 
-fn swap_expression(tokens: &mut Vec<Token>, begin: usize, end: usize, value: f64) {
-    // Remove items between begin and end in tokens and swap with final value
-    tokens.drain(begin..=end);
-    tokens.insert(begin, Token { value: (value.to_string()), ttype: (TokenType::Number), prio: (TokenPrio::NONE) });
+// Names the expression is evaluated with, sheet formulas read missing cells as 0
+fn scope_of<'a>(expr: &Expr, settings: &'a Settings) -> Cow<'a, eval::Scope> {
+    if settings.syntax == Syntax::Sheet {
+        sheet::with_empty_cells(expr, &settings.scope)
+    }
+    else {
+        Cow::Borrowed(&settings.scope)
+    }
 }
 
 fn op_add(a: f64, b: f64) -> f64 {
-    return a + b;
+    a + b
 }
 
 fn op_sub(a: f64, b: f64) -> f64 {
-    return a - b;
+    a - b
 }
 
 fn op_mul(a: f64, b: f64) -> f64 {
-    return a * b;
+    a * b
 }
 
 fn op_exp(a: f64, b: f64) -> f64 {
@@ -242,12 +573,25 @@ fn op_exp(a: f64, b: f64) -> f64 {
 }
 
 fn op_div(a: f64, b: f64) -> f64 {
-    return a / b;
+    a / b
 }
 
 #[cfg(test)]
 mod tests_unit {
     use super::*;
+    use crate::settings::AngleUnit;
+
+    fn calculate(tokens: Vec<Token>, settings: &Settings) -> Result<Value, CalcError> {
+        let expr = parser::parse(&tokens, settings)?;
+        eval::evaluate(&expr, &scope_of(&expr, settings), settings.angle)
+    }
+
+    // Output of each line entered in the session, commands and definitions included
+    fn run_lines(session: &mut repl::Session, lines: &[&str]) -> String {
+        lines.iter()
+            .map(|line| session.command(line).unwrap_or_else(|| session.evaluate(line)))
+            .collect()
+    }
     
     mod add {
         use super::*;
@@ -515,12 +859,12 @@ mod tests_unit {
 
         #[test]
         fn parse_1_token() {
-            let expected = vec![
+            let expected = [
                 Token { value: (String::from("1")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
             
             let input = String::from("1");
-            let actual = parse_tokens(&input);
+            let actual = parse_tokens(&input).unwrap();
     
             assert_eq!(expected.len(), actual.len());
             for i in 0..expected.len() {
@@ -530,14 +874,14 @@ mod tests_unit {
         
         #[test]
         fn parse_3_tokens() {
-            let expected = vec![
+            let expected = [
                 Token { value: (String::from("109")),   ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 Token { value: (String::from("*")),     ttype: (TokenType::Multiply),   prio: (TokenPrio::MUL)  },
                 Token { value: (String::from("15")),    ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
             
             let input = String::from("109 * 15");
-            let actual = parse_tokens(&input);
+            let actual = parse_tokens(&input).unwrap();
     
             assert_eq!(expected.len(), actual.len());
             for i in 0..expected.len() {
@@ -547,7 +891,7 @@ mod tests_unit {
         
         #[test]
         fn parse_parens() {
-            let expected = vec![
+            let expected = [
                 Token { value: (String::from("1")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 Token { value: (String::from("+")),     ttype: (TokenType::Addition),   prio: (TokenPrio::ADD)  },
                 Token { value: (String::from("(")),     ttype: (TokenType::ParenOpen),  prio: (TokenPrio::PAR)  },
@@ -556,7 +900,7 @@ mod tests_unit {
             ];
             
             let input = String::from("1 + ( 3 )");
-            let actual = parse_tokens(&input);
+            let actual = parse_tokens(&input).unwrap();
     
             assert_eq!(expected.len(), actual.len());
             for i in 0..expected.len() {
@@ -566,14 +910,14 @@ mod tests_unit {
         
         #[test]
         fn parse_exponents() {
-            let expected = vec![
+            let expected = [
                 Token { value: (String::from("2")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 Token { value: (String::from("^")),     ttype: (TokenType::Exponent),   prio: (TokenPrio::EXP)  },
                 Token { value: (String::from("4")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
             
             let input = String::from("2 ^ 4");
-            let actual = parse_tokens(&input);
+            let actual = parse_tokens(&input).unwrap();
     
             assert_eq!(expected.len(), actual.len());
             for i in 0..expected.len() {
@@ -583,7 +927,7 @@ mod tests_unit {
         
         #[test]
         fn parse_negatives() {
-            let expected = vec![
+            let expected = [
                 Token { value: (String::from("-1")),    ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 Token { value: (String::from("-")),     ttype: (TokenType::Subtract),   prio: (TokenPrio::SUB)  },
                 Token { value: (String::from("(")),     ttype: (TokenType::ParenOpen),  prio: (TokenPrio::PAR)  },
@@ -592,7 +936,7 @@ mod tests_unit {
             ];
             
             let input = String::from("-1 - ( -1 )");
-            let actual = parse_tokens(&input);
+            let actual = parse_tokens(&input).unwrap();
     
             assert_eq!(expected.len(), actual.len());
             for i in 0..expected.len() {
@@ -602,7 +946,7 @@ mod tests_unit {
 
         #[test]
         fn parse_many() {
-            let expected = vec![
+            let expected = [
                 Token { value: (String::from("-1")),    ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 Token { value: (String::from("+")),     ttype: (TokenType::Addition),   prio: (TokenPrio::ADD)  },
                 Token { value: (String::from("2")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
@@ -621,7 +965,7 @@ mod tests_unit {
             ];
                 
             let input = String::from("-1 + 2 * ( -3 - ( -5 ) ) ^ ( 4 )");
-            let actual = parse_tokens(&input);
+            let actual = parse_tokens(&input).unwrap();
             
             assert_eq!(expected.len(), actual.len());
             for i in 0..expected.len() {
@@ -640,9 +984,9 @@ mod tests_unit {
             let input = vec![
                 Token { value: (String::from("1")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
             
-            assert_eq!(expected, actual);
+            assert_eq!(Ok(Value::Number(expected)), actual);
         }
         
         #[test]
@@ -654,9 +998,9 @@ mod tests_unit {
                 Token { value: (String::from("+")),     ttype: (TokenType::Addition),   prio: (TokenPrio::ADD)  },
                 Token { value: (String::from("19")),    ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
     
-            assert_eq!(expected, actual);
+            assert_eq!(Ok(Value::Number(expected)), actual);
         }
         
         #[test]
@@ -668,9 +1012,9 @@ mod tests_unit {
                 Token { value: (String::from("-")),     ttype: (TokenType::Subtract),   prio: (TokenPrio::SUB)  },
                 Token { value: (String::from("12")),    ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
             
-            assert_eq!(expected, actual);
+            assert_eq!(Ok(Value::Number(expected)), actual);
         }
         
         #[test]
@@ -682,9 +1026,9 @@ mod tests_unit {
                 Token { value: (String::from("*")),     ttype: (TokenType::Multiply),   prio: (TokenPrio::MUL)  },
                 Token { value: (String::from("4")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
             
-            assert_eq!(expected, actual);
+            assert_eq!(Ok(Value::Number(expected)), actual);
         }
         
         #[test]
@@ -696,9 +1040,9 @@ mod tests_unit {
                 Token { value: (String::from("/")),     ttype: (TokenType::Division),   prio: (TokenPrio::DIV)  },
                 Token { value: (String::from("2")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
             
-            assert_eq!(expected, actual);
+            assert_eq!(Ok(Value::Number(expected)), actual);
         }
         
         #[test]
@@ -710,9 +1054,9 @@ mod tests_unit {
                 Token { value: (String::from("^")),     ttype: (TokenType::Exponent),   prio: (TokenPrio::EXP)  },
                 Token { value: (String::from("3")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
             ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
             
-            assert_eq!(expected, actual);
+            assert_eq!(Ok(Value::Number(expected)), actual);
         }
         
         #[test]
@@ -726,9 +1070,9 @@ mod tests_unit {
                 Token { value: (String::from("5")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 Token { value: (String::from(")")),     ttype: (TokenType::ParenClose), prio: (TokenPrio::PAR)  },
             ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
                 
-            assert_eq!(expected, actual);
+            assert_eq!(Ok(Value::Number(expected)), actual);
         }
         
         #[test]
@@ -752,10 +1096,2744 @@ mod tests_unit {
                 Token { value: (String::from("4")),     ttype: (TokenType::Number),     prio: (TokenPrio::NONE) },
                 Token { value: (String::from(")")),     ttype: (TokenType::ParenClose), prio: (TokenPrio::PAR)  },
             ];
-            let actual = calculate(input);
+            let actual = calculate(input, &Settings::default());
             
+            assert_eq!(Ok(Value::Number(expected)), actual);
+        }
+
+        #[test]
+        fn sign_of_literal_before_exponent() {
+            let expected = (Ok(Value::Number(4.)), Ok(Value::Number(-4.)), Ok(Value::Number(-4.)));
+
+            let settings = Settings { scope: eval::Scope::from([(String::from("x"), Value::Number(2.))]), ..Settings::default() };
+            let actual = (evaluate_input("-2^2", &settings).1, evaluate_input("-x^2", &settings).1, evaluate_input("-(2)^2", &settings).1);
+
             assert_eq!(expected, actual);
         }
     }
-}
 
+    mod conditional {
+        use super::*;
+
+        #[test]
+        fn format_keywords() {
+            let expected = "if 1 < 2 then -1 else 2";
+
+            let input = String::from("if 1<2 then -1 else 2");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_comparisons() {
+            let expected = "1 != 2 ? 3 : 4 >= 5";
+
+            let input = String::from("1!=2?3:4>=5");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn ternary_true_branch() {
+            let expected = Ok(Value::Number(3.));
+
+            let input = String::from("1 < 2 ? 3 : 4");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn ternary_false_branch() {
+            let expected = Ok(Value::Number(4.));
+
+            let input = String::from("1 > 2 ? 3 : 4");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn ternary_nested_right_associative() {
+            let expected = Ok(Value::Number(2.));
+
+            let input = String::from("1 > 2 ? 1 : 2 > 1 ? 2 : 3");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn if_then_else() {
+            let expected = Ok(Value::Number(10.));
+
+            let input = String::from("if 3 == 3 then 2 * 5 else 0");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn if_inside_arithmetic() {
+            let expected = Ok(Value::Number(7.));
+
+            let input = String::from("1 + (if 2 <= 1 then 0 else 6)");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn untaken_branch_is_not_evaluated() {
+            let expected = Ok(Value::Number(0.));
+
+            let input = String::from("0 != 0 ? 1 / 0 : 0");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn taken_branch_is_evaluated() {
+            let expected = Err(CalcError::new("Division by zero"));
+
+            let input = String::from("0 == 0 ? 1 / 0 : 0");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn comparison_result_is_bool() {
+            let expected = Ok(Value::Bool(true));
+
+            let input = String::from("2 * 3 > 5");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn number_condition_is_error() {
+            let expected = Err(CalcError::new("Condition must be true or false, found 1"));
+
+            let input = String::from("1 ? 2 : 3");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn missing_else_is_error() {
+            let expected = Err(CalcError::at(6, "Expected 'else' before end of expression"));
+
+            let input = String::from("if 1 < 2 then 3");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn missing_colon_is_error() {
+            let expected = Err(CalcError::at(5, "Expected ':' but found 'else'"));
+
+            let input = String::from("1 < 2 ? 3 else 4");
+            let actual = parse_tokens(&format_tokens(&input)).and_then(|tokens| calculate(tokens, &Settings::default()));
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod implicit_mul {
+        use super::*;
+        use crate::settings::ImplicitMul;
+
+        fn calc_str(input: &str, implicit: ImplicitMul) -> Result<Value, CalcError> {
+            let settings = Settings { implicit, ..Settings::default() };
+            parse_tokens(&format_tokens(input)).and_then(|tokens| calculate(tokens, &settings))
+        }
+
+        #[test]
+        fn format_number_and_name() {
+            let expected = "2 pi ( 1 + 2 )";
+
+            let input = String::from("2pi(1+2)");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn number_and_constant() {
+            let expected = Ok(Value::Number(2. * std::f64::consts::PI));
+
+            let actual = calc_str("2pi", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn number_and_paren() {
+            let expected = Ok(Value::Number(27.));
+
+            let actual = calc_str("3(4+5)", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn paren_and_paren() {
+            let expected = Ok(Value::Number(-3.));
+
+            let actual = calc_str("(1+2)(3-4)", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn paren_and_number() {
+            let expected = Ok(Value::Number(9.));
+
+            let actual = calc_str("(1+2)3", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn number_and_function() {
+            let expected = Ok(Value::Number(6.));
+
+            let actual = calc_str("2sqrt(9)", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn standard_binds_like_multiply() {
+            let expected = Ok(Value::Number(9.));
+
+            let actual = calc_str("6/2(1+2)", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn tight_binds_before_divide() {
+            let expected = Ok(Value::Number(1.));
+
+            let actual = calc_str("6/2(1+2)", ImplicitMul::Tight);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn exponent_binds_before_implicit() {
+            let expected = Ok(Value::Number(12.));
+
+            let actual = calc_str("3(2)^2", ImplicitMul::Tight);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn two_numbers_is_error() {
+            let expected = Err(CalcError::at(1, "Missing operator between '2' and '3'"));
+
+            let actual = calc_str("2 3", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn name_and_number_is_error() {
+            let expected = Err(CalcError::at(1, "Missing operator between 'pi' and '2'"));
+
+            let actual = calc_str("pi 2", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn constant_and_paren_is_error() {
+            let expected = Err(CalcError::at(2, "'e' is not a function, use '*' to multiply"));
+
+            let actual = calc_str("2 + e(2)", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn function_without_paren_is_error() {
+            let expected = Err(CalcError::at(0, "Function sqrt must be called with parentheses, such as sqrt(x)"));
+
+            let actual = calc_str("sqrt 4", ImplicitMul::Standard);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod functions {
+        use super::*;
+
+        fn calc_str(input: &str) -> Result<Value, CalcError> {
+            parse_tokens(&format_tokens(input)).and_then(|tokens| calculate(tokens, &Settings::default()))
+        }
+
+        #[test]
+        fn two_arguments() {
+            let expected = Ok(Value::Number(3.));
+
+            let actual = calc_str("max(1 + 1, 3)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn nested_calls() {
+            let expected = Ok(Value::Number(3.));
+
+            let actual = calc_str("sqrt(abs(-9))");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn wrong_argument_count_is_error() {
+            let expected = Err(CalcError::at(0, "atan2(y, x) takes 2 argument(s) but 1 were given"));
+
+            let actual = calc_str("atan2(1)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn any_number_of_values() {
+            let expected = (Ok(Value::Number(1.)), Ok(Value::Number(10.)), Err(CalcError::at(0, "min(a, b, ...) takes at least 2 argument(s) but 1 were given")));
+
+            let actual = (calc_str("min(4, 1, 3, 2)"), calc_str("sum(1, 2, 3, 4)"), calc_str("min(4)"));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn angles_in_degrees() {
+            let expected = [Ok(Value::Number(1.)), Ok(Value::Number(-1.)), Ok(Value::Number(90.)), Ok(Value::Number(45.))];
+
+            let settings = Settings { angle: AngleUnit::Degrees, ..Settings::default() };
+            let actual = ["sin(90)", "cos(180)", "acos(0)", "atan2(1, 1)"].map(|input| evaluate_input(input, &settings).1);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unknown_name_is_error() {
+            let expected = Err(CalcError::new("Unknown name: x"));
+
+            let actual = calc_str("2x");
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod unicode {
+        use super::*;
+
+        fn calc_str(input: &str) -> Result<Value, CalcError> {
+            parse_tokens(&format_tokens(input)).and_then(|tokens| calculate(tokens, &Settings::default()))
+        }
+
+        #[test]
+        fn format_operators() {
+            let expected = "2 * 3 / 4 - 1 * 5";
+
+            let input = String::from("2×3÷4−1·5");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_superscripts() {
+            let expected = "x ^ 23 + y ^ -1";
+
+            let input = String::from("x²³ + y⁻¹");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_pi_between_names() {
+            let expected = "2 pi r";
+
+            let input = String::from("2πr");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_comparisons() {
+            let expected = "1 <= 2 != 3 >= 4";
+
+            let input = String::from("1≤2≠3≥4");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn negative_with_unicode_minus() {
+            let expected = Ok(Value::Number(-6.));
+
+            let actual = calc_str("−2×3");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn superscript_exponent() {
+            let expected = Ok(Value::Number(10.));
+
+            let actual = calc_str("3² + 1");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn negative_superscript_exponent() {
+            let expected = Ok(Value::Number(0.25));
+
+            let actual = calc_str("2⁻²");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn pi_constant() {
+            let expected = Ok(Value::Number(std::f64::consts::PI));
+
+            let actual = calc_str("π");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn root_of_number() {
+            let expected = Ok(Value::Number(4.));
+
+            let actual = calc_str("√16");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn root_of_group() {
+            let expected = Ok(Value::Number(5.));
+
+            let actual = calc_str("√(3² + 4²)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn root_covers_exponent() {
+            let expected = Ok(Value::Number(4.));
+
+            let actual = calc_str("√4²");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn number_before_root() {
+            let expected = Ok(Value::Number(7.));
+
+            let actual = calc_str("2√9 + 1");
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod delimiters {
+        use super::*;
+
+        fn calc_str(input: &str) -> Result<Value, CalcError> {
+            parse_tokens(&format_tokens(input)).and_then(|tokens| calculate(tokens, &Settings::default()))
+        }
+
+        #[test]
+        fn format_bars_keep_minus_apart() {
+            let expected = "| x | - 3";
+
+            let input = String::from("|x|-3");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn abs_of_difference() {
+            let expected = Ok(Value::Number(4.));
+
+            let actual = calc_str("|1 - 5|");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn abs_then_subtract() {
+            let expected = Ok(Value::Number(-1.));
+
+            let actual = calc_str("|-2|-3");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn abs_subtract_abs() {
+            let expected = Ok(Value::Number(-1.));
+
+            let actual = calc_str("|-2|-|3|");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn nested_abs() {
+            let expected = Ok(Value::Number(1.));
+
+            let actual = calc_str("||-2|-3|");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn abs_inside_parens_inside_abs() {
+            let expected = Ok(Value::Number(6.));
+
+            let actual = calc_str("|(-2|3|)|");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn number_before_abs() {
+            let expected = Ok(Value::Number(6.));
+
+            let actual = calc_str("2|-3|");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn floor_and_ceil() {
+            let expected = Ok(Value::Number(5.));
+
+            let actual = calc_str("⌊2.7⌋ + ⌈2.1⌉");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unclosed_bar_is_error() {
+            let expected = Err(CalcError::at(2, "'|' is never closed, expected '|'"));
+
+            let actual = calc_str("1 + |2");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn mismatched_floor_is_error() {
+            let expected = Err(CalcError::at(2, "'⌉' cannot close '⌊', expected '⌋'"));
+
+            let actual = calc_str("⌊2⌉");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn paren_closed_by_ceil_is_error() {
+            let expected = Err(CalcError::at(3, "'⌉' cannot close '(', expected ')'"));
+
+            let actual = calc_str("⌈(1⌉");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unmatched_close_is_error() {
+            let expected = Err(CalcError::at(3, "Unmatched '⌋'"));
+
+            let actual = calc_str("1 + 2⌋");
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod brackets {
+        use super::*;
+
+        fn calc_str(input: &str) -> Result<Value, CalcError> {
+            parse_tokens(&format_tokens(input)).and_then(|tokens| calculate(tokens, &Settings::default()))
+        }
+
+        #[test]
+        fn format_nested_kinds() {
+            let expected = "{ [ ( -1 + 2 ) * 3 ] - 4 } - 5";
+
+            let input = String::from("{[(-1+2)*3]-4}-5");
+            let actual = format_tokens(&input);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn parse_bracket_kinds() {
+            let expected = [
+                Token { value: (String::from("[")),     ttype: (TokenType::BracketOpen),    prio: (TokenPrio::PAR)  },
+                Token { value: (String::from("{")),     ttype: (TokenType::BraceOpen),      prio: (TokenPrio::PAR)  },
+                Token { value: (String::from("1")),     ttype: (TokenType::Number),         prio: (TokenPrio::NONE) },
+                Token { value: (String::from("}")),     ttype: (TokenType::BraceClose),     prio: (TokenPrio::PAR)  },
+                Token { value: (String::from("]")),     ttype: (TokenType::BracketClose),   prio: (TokenPrio::PAR)  },
+            ];
+
+            let input = String::from("[ { 1 } ]");
+            let actual = parse_tokens(&input).unwrap();
+
+            assert_eq!(expected.len(), actual.len());
+            for i in 0..expected.len() {
+                assert_eq!(&expected[i], &actual[i]);
+            }
+        }
+
+        #[test]
+        fn nested_kinds() {
+            let expected = Ok(Value::Number(-1.));
+
+            let actual = calc_str("{[(1 + 2) * 3] - 4} - 6");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn implicit_multiply_across_kinds() {
+            let expected = Ok(Value::Number(12.));
+
+            let actual = calc_str("[1 + 2]{2 + 2}");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn paren_closed_by_bracket_is_error() {
+            let expected = Err(CalcError::at(5, "']' cannot close '(', expected ')'"));
+
+            let actual = calc_str("[(1 + 2] * 3)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn brace_closed_by_paren_is_error() {
+            let expected = Err(CalcError::at(4, "')' cannot close '{', expected '}'"));
+
+            let actual = calc_str("2 * {3)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn missing_close_points_at_open() {
+            let expected = Err(CalcError::at(2, "'(' is never closed, expected ')'"));
+
+            let actual = calc_str("1 + (2 * 3");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn extra_close_is_error() {
+            let expected = Err(CalcError::at(5, "Unmatched ']'"));
+
+            let actual = calc_str("[1 + 2]]");
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod cli_args {
+        use super::*;
+
+        fn args_of(args: &[&str]) -> Result<cli::Args, CalcError> {
+            cli::parse_args(args.iter().map(|arg| arg.to_string()), Settings::default())
+        }
+
+        #[test]
+        fn no_arguments_is_interactive() {
+            let expected = None;
+
+            let actual = args_of(&[]).unwrap().expression;
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn single_expression() {
+            let expected = Some(String::from("2*(3+4)"));
+
+            let actual = args_of(&["2*(3+4)"]).unwrap().expression;
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn split_expression_is_joined() {
+            let expected = Some(String::from("2 + 3"));
+
+            let actual = args_of(&["2", "+", "3"]).unwrap().expression;
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn negative_number_is_not_option() {
+            let expected = Some(String::from("-3 + 4"));
+
+            let actual = args_of(&["-3", "+", "4"]).unwrap().expression;
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn explain_needs_single_expression() {
+            let expected = (Err(String::from("--explain works on a single expression with plain output")), Ok(true));
+
+            let actual = (
+                args_of(&["--explain", "-f", "exprs.txt"]).map(|args| args.explain).map_err(|error| error.to_string()),
+                args_of(&["--explain", "1 + 2"]).map(|args| args.explain).map_err(|error| error.to_string()),
+            );
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn double_dash_ends_options() {
+            let expected = Some(String::from("-pi"));
+
+            let actual = args_of(&["--", "-pi"]).unwrap().expression;
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn option_before_expression() {
+            let expected = Ok(Value::Number(1.));
+
+            let args = args_of(&["--implicit", "tight", "6/2(1+2)"]).unwrap();
+            let (_, actual) = evaluate_input(&args.expression.unwrap(), &args.settings);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unknown_option_is_error() {
+            let expected = Err(CalcError::new("Unknown option: --bogus"));
+
+            let actual = args_of(&["--bogus"]).map(|args| args.expression);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn missing_option_value_is_error() {
+            let expected = Err(CalcError::new("--implicit requires a value"));
+
+            let actual = args_of(&["--implicit"]).map(|args| args.expression);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn error_report_points_at_token() {
+            let expected = "1 + * 2\n    ^\nERROR: Unexpected token: *\n";
+
+            let (formatted, result) = evaluate_input("1 + * 2", &Settings::default());
+            let actual = error_report(&formatted, &result.unwrap_err());
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod batch_lines {
+        use super::*;
+        use std::io::Cursor;
+
+        fn run_lines(input: &str, keep_going: bool) -> (String, String, batch::Summary, bool) {
+            let mut out = Vec::<u8>::new();
+            let mut err = Vec::<u8>::new();
+            let mut summary = batch::Summary::default();
+            let mut session = repl::Session::new(Settings::default());
+            let completed = batch::evaluate_lines(Cursor::new(input), "exprs.txt", &mut session, keep_going, &mut out, &mut err, &mut summary).unwrap();
+
+            (String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap(), summary, completed)
+        }
+
+        #[test]
+        fn one_result_per_line() {
+            let expected = "3\n12\n";
+
+            let (actual, _, _, _) = run_lines("1 + 2\n3 * 4\n", false);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn skips_comments_and_blank_lines() {
+            let expected = batch::Summary { evaluated: 2, failed: 0 };
+
+            let (_, _, actual, _) = run_lines("# prices\n\n1 + 2\n   \n  # total\n3 * 4\n", false);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn stops_at_first_error() {
+            let expected = ("1\n".to_string(), batch::Summary { evaluated: 2, failed: 1 }, false);
+
+            let (out, _, summary, completed) = run_lines("1\n1 / 0\n2\n", false);
+            let actual = (out, summary, completed);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn keep_going_continues_after_error() {
+            let expected = ("1\n2\n".to_string(), batch::Summary { evaluated: 3, failed: 1 }, true);
+
+            let (out, _, summary, completed) = run_lines("1\n1 / 0\n2\n", true);
+            let actual = (out, summary, completed);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn errors_report_line_numbers() {
+            let expected = "exprs.txt:3: ERROR: Division by zero\nexprs.txt:4: ERROR: Unexpected end of expression\n2 +\n    ^\n";
+
+            let (_, actual, _, _) = run_lines("# header\n1\n1 / 0\n2 +\n", true);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod output_format {
+        use super::*;
+        use crate::settings::NumberFormat;
+
+        fn render_input(input: &str, format: OutputFormat) -> String {
+            let (formatted, result) = evaluate_input(input, &Settings::default());
+            let record = output::Record { input, expression: &formatted, result: &result, number: &NumberFormat::default() };
+            output::render(format, &record)
+        }
+
+        #[test]
+        fn json_number() {
+            let expected = r#"{"input":"2*(3+4)","expression":"2 * ( 3 + 4 )","result":14,"formatted":"14","type":"number","error":null,"warnings":[]}"#;
+
+            let actual = render_input("2*(3+4)", OutputFormat::Json);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn json_boolean() {
+            let expected = r#"{"input":"1<2","expression":"1 < 2","result":true,"formatted":"true","type":"boolean","error":null,"warnings":[]}"#;
+
+            let actual = render_input("1<2", OutputFormat::Json);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn json_error_with_column() {
+            let expected = r#"{"input":"1 + * 2","expression":"1 + * 2","result":null,"formatted":null,"type":null,"error":{"message":"Unexpected token: *","column":5},"warnings":[]}"#;
+
+            let actual = render_input("1 + * 2", OutputFormat::Json);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn json_infinite_is_null_with_warning() {
+            let expected = r#"{"input":"10^400","expression":"10 ^ 400","result":null,"formatted":"inf","type":"number","error":null,"warnings":["Result is not a finite number: inf"]}"#;
+
+            let actual = render_input("10^400", OutputFormat::Json);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn csv_header() {
+            let expected = Some(String::from("input,expression,result,formatted,type,error,warnings"));
+
+            let actual = output::header(OutputFormat::Csv);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn csv_quotes_commas() {
+            let expected = r#""max(1,2)","max ( 1 , 2 )",2,2,number,,"#;
+
+            let actual = render_input("max(1,2)", OutputFormat::Csv);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn csv_error() {
+            let expected = "1/0,1 / 0,,,,Division by zero,";
+
+            let actual = render_input("1/0", OutputFormat::Csv);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn tsv_row() {
+            let expected = "1+1\t1 + 1\t2\t2\tnumber\t\t";
+
+            let actual = render_input("1+1", OutputFormat::Tsv);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod number_format {
+        use super::*;
+        use crate::settings::{Notation, NumberFormat, Precision};
+
+        fn format_with(x: f64, notation: Notation, precision: Precision) -> String {
+            notation::format_number(x, &NumberFormat { notation, precision, ..NumberFormat::default() })
+        }
+
+        fn calc_str(input: &str) -> Result<Value, CalcError> {
+            parse_tokens(&format_tokens(input)).and_then(|tokens| calculate(tokens, &Settings::default()))
+        }
+
+        #[test]
+        fn shortest_matches_display() {
+            let expected = "0.30000000000000004";
+
+            let actual = format_with(0.1 + 0.2, Notation::Auto, Precision::Shortest);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn exponents_read_back() {
+            let values = [1234.5, -0.000_012_5, 6.02e23];
+            let expected: Vec<Result<Value, CalcError>> = values.iter().flat_map(|x| [Ok(Value::Number(*x)), Ok(Value::Number(*x))]).collect();
+
+            let actual: Vec<Result<Value, CalcError>> = values.iter()
+                .flat_map(|x| [Notation::Scientific, Notation::Engineering].map(|notation| calc_str(&format_with(*x, notation, Precision::Shortest))))
+                .collect();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn bare_e_is_the_constant() {
+            let expected = (Ok(Value::Number(2. * std::f64::consts::E)), Ok(Value::Number(2. * std::f64::consts::E - 1.)), Ok(Value::Number(0.2)));
+
+            let actual = (calc_str("2e"), calc_str("2e - 1"), calc_str("2e-1"));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn digits_are_capped() {
+            let expected = vec![
+                String::from("--digits must be at most 767, found 3000000000"),
+                String::from("Digits after the point must be at most 767"),
+                String::from("Significant figures must be at most 767"),
+                String::from("Digits after the point must be at most 767"),
+            ];
+
+            let actual = vec![
+                cli::parse_args(["--digits", "3000000000", "1"].iter().map(|arg| arg.to_string()), Settings::default()).unwrap_err().to_string(),
+                Settings::default().set("digits", "768").unwrap_err().to_string(),
+                NumberFormat::from_spec("sig=4294967297").unwrap_err().to_string(),
+                calc_str("format(1, \"digits=1000\")").unwrap_err().to_string(),
+            ];
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn large_number_is_positional() {
+            let expected = "1000000000000000000000";
+
+            let actual = format_with(1e21, Notation::Auto, Precision::Shortest);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn decimals_round_half_away_from_zero() {
+            let expected = "0.13";
+
+            let actual = format_with(0.125, Notation::Auto, Precision::Decimals(2));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn decimals_use_exact_binary_value() {
+            // 2.675 is stored as 2.67499999999999982236431605997495353221893310546875
+            let expected = "2.67";
+
+            let actual = format_with(2.675, Notation::Auto, Precision::Decimals(2));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn decimals_pad_with_zeros() {
+            let expected = "-3.500";
+
+            let actual = format_with(-3.5, Notation::Auto, Precision::Decimals(3));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn decimals_carry_into_new_digit() {
+            let expected = "10.0";
+
+            let actual = format_with(9.96, Notation::Auto, Precision::Decimals(1));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn rounded_to_zero_has_no_sign() {
+            let expected = "0.00";
+
+            let actual = format_with(-0.001, Notation::Auto, Precision::Decimals(2));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn significant_keeps_trailing_zeros() {
+            let expected = "0.1000";
+
+            let actual = format_with(0.1 + 0.2 - 0.2, Notation::Auto, Precision::Significant(4));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn significant_of_large_number() {
+            let expected = "1230000";
+
+            let actual = format_with(1234567., Notation::Auto, Precision::Significant(3));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn scientific_shortest() {
+            let expected = "1.2345e4";
+
+            let actual = format_with(12345., Notation::Scientific, Precision::Shortest);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn scientific_significant() {
+            let expected = "-1.23e-4";
+
+            let actual = format_with(-0.00012345, Notation::Scientific, Precision::Significant(3));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn scientific_zero() {
+            let expected = "0.00e0";
+
+            let actual = format_with(0., Notation::Scientific, Precision::Decimals(2));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn engineering_small() {
+            let expected = "123.4e-6";
+
+            let actual = format_with(0.0001234, Notation::Engineering, Precision::Shortest);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn engineering_rounds_into_next_exponent() {
+            let expected = "1.0e3";
+
+            let actual = format_with(999.96, Notation::Engineering, Precision::Decimals(1));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn engineering_significant() {
+            let expected = "12.30e3";
+
+            let actual = format_with(12300., Notation::Engineering, Precision::Significant(4));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn infinity_is_unchanged() {
+            let expected = "inf";
+
+            let actual = format_with(f64::INFINITY, Notation::Scientific, Precision::Decimals(2));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn spec_combines_items() {
+            let expected = Ok(NumberFormat { notation: Notation::Scientific, precision: Precision::Significant(3), ..NumberFormat::default() });
+
+            let actual = NumberFormat::from_spec("sci, sig=3");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn spec_unknown_item_is_error() {
+            let expected = Err(CalcError::new("Unknown number format: fixed (expected auto, sci, eng, digits=N or sig=N)"));
+
+            let actual = NumberFormat::from_spec("fixed");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_function() {
+            let expected = Ok(Value::Text(String::from("3.14")));
+
+            let actual = calc_str("format(pi, \"sig=3\")");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_function_text_keeps_spaces() {
+            let expected = Ok(Value::Text(String::from("1.23e3")));
+
+            let actual = calc_str("format(1234, \"sci, digits=2\")");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_function_needs_text_spec() {
+            let expected = Err(CalcError::new("Expected text but found 3"));
+
+            let actual = calc_str("format(pi, 3)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unterminated_text_is_error() {
+            let expected = Err(CalcError::at(4, "Unterminated text, expected a closing '\"'"));
+
+            let actual = calc_str("format(pi, \"sig=3)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn parse_significant_option() {
+            let expected = NumberFormat { notation: Notation::Engineering, precision: Precision::Significant(2), ..NumberFormat::default() };
+
+            let args = cli::parse_args(["--sig", "2", "--eng", "1"].iter().map(|arg| arg.to_string()), Settings::default()).unwrap();
+            let actual = args.settings.number;
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod locale {
+        use super::*;
+        use crate::locale::Locale;
+        use crate::settings::NumberFormat;
+
+        fn locale(name: &str) -> Locale {
+            Locale::from_name(name).unwrap()
+        }
+
+        fn calc_in(input: &str, name: &str) -> String {
+            let settings = Settings { number: NumberFormat { locale: locale(name), ..NumberFormat::default() }, ..Settings::default() };
+            let (_, result) = evaluate_input(input, &settings);
+            result.unwrap().display(&settings.number)
+        }
+
+        #[test]
+        fn delocalize_decimal_comma() {
+            let expected = "1234.56 + 2.5";
+
+            let actual = locale("de").delocalize("1.234,56 + 2,5");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn delocalize_argument_separator() {
+            let expected = "max(1.5, 2)";
+
+            let actual = locale("de").delocalize("max(1,5; 2)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn delocalize_space_group() {
+            let expected = "1234.56 * 2";
+
+            let actual = locale("fr").delocalize("1 234,56 * 2");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn delocalize_short_group_is_kept() {
+            let expected = "2 3";
+
+            let actual = locale("fr").delocalize("2 3");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn delocalize_leaves_text() {
+            let expected = "format(1.5, \"sig=2; eng\")";
+
+            let actual = locale("de").delocalize("format(1,5; \"sig=2; eng\")");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn delocalize_en_keeps_commas() {
+            let expected = "max(1,234)";
+
+            let actual = locale("en").delocalize("max(1,234)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn localize_de() {
+            let expected = "-1.234.567,891";
+
+            let actual = locale("de").localize("-1234567.891");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn localize_fr() {
+            let expected = "1 234,56";
+
+            let actual = locale("fr").localize("1234.56");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn localize_en_short_number() {
+            let expected = "123.5";
+
+            let actual = locale("en").localize("123.5");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn evaluate_in_de() {
+            let expected = "2.469,12";
+
+            let actual = calc_in("1.234,56 * 2", "de");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn evaluate_in_fr() {
+            let expected = "1 234,5";
+
+            let actual = calc_in("max(1 000; 1 234,5)", "fr");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unknown_locale() {
+            let expected = Err(CalcError::new("Unknown locale: xx (expected c, en, ch, de or fr)"));
+
+            let actual = Locale::from_name("xx");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn parse_locale_option() {
+            let expected = locale("ch");
+
+            let args = cli::parse_args(["--locale", "ch", "1"].iter().map(|arg| arg.to_string()), Settings::default()).unwrap();
+            let actual = args.settings.number.locale;
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod explain_steps {
+        use super::*;
+        use crate::eval::Scope;
+
+        fn parse_str(input: &str) -> Expr {
+            parse_input(input, &Settings::default()).1.unwrap()
+        }
+
+        fn print_str(input: &str) -> String {
+            printer::print(&parse_str(input))
+        }
+
+        fn expressions(input: &str) -> Vec<String> {
+            let (steps, _) = explain::explain(&parse_str(input), &Scope::new(), AngleUnit::Radians);
+            steps.into_iter().map(|step| step.expression).collect()
+        }
+
+        #[test]
+        fn print_drops_redundant_parens() {
+            let expected = "2 + 3 * 4";
+
+            let actual = print_str("(2 + (3 * 4))");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn print_keeps_needed_parens() {
+            let expected = "(2 + 3) * (4 - 1) / (5 - (6 - 7))";
+
+            let actual = print_str("(2+3)(4-1)/(5-(6-7))");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn print_exponent_associativity() {
+            let expected = "(2 ^ 3) ^ 2 + 2 ^ 3 ^ 2";
+
+            let actual = print_str("(2^3)^2 + 2^(3^2)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn print_negated_number_keeps_parens() {
+            let expected = "-(2 ^ 2) + -x";
+
+            let actual = print_str("-(2^2) + -pi").replace("pi", "x");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn print_conditional() {
+            let expected = "1 < 2 ? sqrt(4) : abs(-3)";
+
+            let actual = print_str("if 1 < 2 then √4 else |-3|");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn steps_follow_precedence() {
+            let expected = ["2 + 12", "14"];
+
+            let actual = expressions("2 + 3 * 4");
+
+            assert_eq!(expected.as_slice(), actual.as_slice());
+        }
+
+        #[test]
+        fn steps_left_to_right() {
+            let expected = ["6 - 4 * 5", "6 - 20", "-14"];
+
+            let actual = expressions("2 * 3 - 4 * 5");
+
+            assert_eq!(expected.as_slice(), actual.as_slice());
+        }
+
+        #[test]
+        fn step_rule() {
+            let expected = "multiplication: 3 * 4 = 12";
+
+            let (steps, _) = explain::explain(&parse_str("2 + 3 * 4"), &Scope::new(), AngleUnit::Radians);
+            let actual = &steps[0].rule;
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn conditional_skips_other_branch() {
+            let expected = ["false ? 1 / 0 : 2", "2"];
+
+            let actual = expressions("1 > 2 ? 1 / 0 : 2");
+
+            assert_eq!(expected.as_slice(), actual.as_slice());
+        }
+
+        #[test]
+        fn failing_step_keeps_earlier_steps() {
+            let expected = (1, Err(CalcError::new("Division by zero")));
+
+            let (steps, result) = explain::explain(&parse_str("1 / (3 - 3)"), &Scope::new(), AngleUnit::Radians);
+            let actual = (steps.len(), result);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn render_aligns_rules() {
+            let expected = "  2 + 3 * 4\n→ 2 + 12      multiplication: 3 * 4 = 12\n→ 14          addition: 2 + 12 = 14\n";
+
+            let (steps, _) = explain::explain(&parse_str("2 + 3 * 4"), &Scope::new(), AngleUnit::Radians);
+            let actual = explain::render("2 + 3 * 4", &steps);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod formatter {
+        use super::*;
+        use crate::settings::{ImplicitMul, Style};
+        use std::io::Cursor;
+
+        const COMPACT: Style = Style { spaces: false, times: '×' };
+
+        fn fmt_str(input: &str, style: &Style) -> String {
+            crate::formatter::format_expression(input, &mut Settings::default(), style).1.unwrap()
+        }
+
+        fn fmt_lines(input: &str, check: bool) -> (String, String, bool) {
+            let mut out = Vec::<u8>::new();
+            let mut err = Vec::<u8>::new();
+            let mut settings = Settings::default();
+            let mut rewrite = |line: &str| crate::formatter::format_expression(line, &mut settings, &Style::default());
+            let succeeded = crate::formatter::rewrite_lines(Cursor::new(input), "f.txt", &mut rewrite, check, &mut out, &mut err).unwrap();
+
+            (String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap(), succeeded)
+        }
+
+        #[test]
+        fn spaces_and_explicit_multiplication() {
+            let expected = "2 * (3 + 4) / x - sqrt(2) * pi";
+
+            let actual = fmt_str("2(3+4)/x-√2π", &Style::default());
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn compact_with_times() {
+            let expected = "2×(3+4)/x-max(1,2)";
+
+            let actual = fmt_str("2 (3 + 4) / x - max(1, 2)", &COMPACT);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn tight_implicit_keeps_meaning() {
+            let expected = "6 / (2 * (1 + 2))";
+
+            let mut settings = Settings { implicit: ImplicitMul::Tight, ..Settings::default() };
+            let actual = crate::formatter::format_expression("6/2(1+2)", &mut settings, &Style::default()).1.unwrap();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn idempotent() {
+            let inputs = [
+                "2+3*4", "(2+3)*4", "2^3^2", "(2^3)^2", "-2^2", "-(2^2)", "--x", "2 - -3", "x^-2",
+                "(1 < 2) == (3 < 4)", "if 1 < 2 then 3 else 4", "1 ? 2 ? 3 : 4 : 5 ? 6 : 7", "(1 ? 2 : 3) ? 4 : 5",
+                "|-3| + ⌊2.5⌋", "format(1/3, \"sig=2\")", "0.1 + 0.2", "1e300 * 10", "2(3)(4)", "e^(2pi)",
+            ];
+
+            for style in [Style::default(), COMPACT] {
+                for input in inputs {
+                    let expected = fmt_str(input, &style);
+
+                    let actual = fmt_str(&expected, &style);
+
+                    assert_eq!(expected, actual);
+                }
+            }
+        }
+
+        #[test]
+        fn lines_keep_comments_and_blank_lines() {
+            let expected = "# totals\n2 + 3\n\n1 * 2\n";
+
+            let (actual, _, _) = fmt_lines("# totals\n2+3\n\n  (1)*(2)\n", false);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn failing_line_is_kept() {
+            let expected = ("1 +\n2\n".to_string(), false);
+
+            let (out, _, succeeded) = fmt_lines("1 +\n2\n", false);
+            let actual = (out, succeeded);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn check_reports_changes() {
+            let expected = (String::new(), "f.txt:2: not formatted, expected: 1 * 2\n".to_string(), false);
+
+            let actual = fmt_lines("2 + 3\n1*2\n", true);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn script_definitions_and_settings() {
+            let expected = (String::from("w = 1 / 3\nf(x, y) = x ^ 2 + y\n:set digits 2\nw * f(3, 1)\n"), String::new(), true);
+
+            let actual = fmt_lines("w=1/3\nf(x,y)=x^2+y\n:set digits 2\nw*f(3,1)\n", false);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn check_passes_formatted_lines() {
+            let expected = (String::new(), String::new(), true);
+
+            let actual = fmt_lines("# ok\n2 + 3\n", true);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn parse_fmt_command() {
+            let expected = (cli::Command::Format, COMPACT, Some(String::from("2+3")));
+
+            let args = cli::parse_args(["fmt", "--compact", "--times", "2+3"].iter().map(|arg| arg.to_string()), Settings::default()).unwrap();
+            let actual = (args.command, args.style, args.expression);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn format_option_needs_fmt() {
+            let expected = Err(CalcError::new("--compact only applies to fmt"));
+
+            let actual = cli::parse_args(["--compact", "2+3"].iter().map(|arg| arg.to_string()), Settings::default()).map(|args| args.command);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod latex_output {
+        use super::*;
+        use crate::settings::{Notation, NumberFormat};
+
+        fn latex_str(input: &str) -> String {
+            latex::to_latex(&parse_input(input, &Settings::default()).1.unwrap())
+        }
+
+        #[test]
+        fn fraction_and_root() {
+            let expected = r"\frac{1}{2} + \sqrt{3} \cdot \pi";
+
+            let actual = latex_str("1/2 + √3 π");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn fraction_groups_its_operands() {
+            let expected = r"\frac{1 + 2}{3 \cdot 4} \cdot 5";
+
+            let actual = latex_str("(1 + 2) / (3 * 4) * 5");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn text_escapes_specials() {
+            let expected = r"\text{50\%} \mathbin{\&} \text{\textbackslash{}a\_b\{c\} \$\#\^{}\~{}}";
+
+            let actual = latex_str(r#""50%" & "\a_b{c} $#^~""#);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn exponent_in_braces() {
+            let expected = r"\left(1 + 2\right)^{2 + 1} - 2^{3^{2}}";
+
+            let actual = latex_str("(1 + 2)^(2 + 1) - 2^3^2");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn negative_operands_are_wrapped() {
+            let expected = r"\left(-2\right)^{2} - \left(-3\right)";
+
+            let actual = latex_str("-2^2 - -3");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn functions() {
+            let expected = r"\left|x\right| + \sqrt[3]{8} + \sin\left(x\right) + \operatorname{atan2}\left(1, 2\right)";
+
+            let actual = latex_str("|x| + cbrt(8) + sin(x) + atan2(1, 2)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn conditional_as_cases() {
+            let expected = r"\begin{cases} 1 & \text{if } x \leq 2 \\ 0 & \text{otherwise} \end{cases}";
+
+            let actual = latex_str("x <= 2 ? 1 : 0");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn scientific_result() {
+            let expected = r"1.5 \times 10^{-3}";
+
+            let number = NumberFormat { notation: Notation::Scientific, ..NumberFormat::default() };
+            let actual = latex::value_latex(&Value::Number(0.0015), &number);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn latex_and_explain_conflict() {
+            let expected = Err(CalcError::new("--explain cannot be combined with --latex"));
+
+            let actual = cli::parse_args(["--latex", "--explain", "1"].iter().map(|arg| arg.to_string()), Settings::default()).map(|args| args.latex);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod latex_input {
+        use super::*;
+
+        fn latex_settings() -> Settings {
+            Settings { syntax: Syntax::Latex, ..Settings::default() }
+        }
+
+        fn calc_latex(input: &str) -> Result<Value, CalcError> {
+            evaluate_input(input, &latex_settings()).1
+        }
+
+        fn same_tree(latex: &str, plain: &str) -> bool {
+            parse_input(latex, &latex_settings()).1.unwrap() == parse_input(plain, &Settings::default()).1.unwrap()
+        }
+
+        #[test]
+        fn fraction_root_and_pi() {
+            let expected = Ok(Value::Number(0.5 + 3f64.sqrt() * std::f64::consts::PI));
+
+            let actual = calc_latex(r"\frac{1}{2} + \sqrt{3} \cdot \pi");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn reads_back_written_latex() {
+            let plain = ["x <= 2 ? 1 : 0", "\"a\" & 2", "x >= 1 ? (y < 2 ? 1 : 2) : \"n\" & 1", "\"50%\" & \"\\a_b{c} $#^~\""];
+            let expected = vec![true; plain.len()];
+
+            let actual: Vec<bool> = plain.iter()
+                .map(|plain| same_tree(&latex::to_latex(&parse_input(plain, &Settings::default()).1.unwrap()), plain))
+                .collect();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn formatted_as_plain_tokens() {
+            let expected = "( ( 1 ) / ( 2 ) ) + sqrt ( 3 ) * pi";
+
+            let (actual, _) = evaluate_input(r"\frac{1}{2} + \sqrt{3} \cdot \pi", &latex_settings());
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn signed_literal_before_exponent() {
+            let expected = (Ok(Value::Number(4.)), true);
+
+            let actual = (calc_latex("-2^{2}"), same_tree("-2^{2}", "-2^2"));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn same_tree_as_plain_syntax() {
+            let expected = true;
+
+            let actual = same_tree(r"\left(1 + 2\right)^{2} \times 3", "((1 + 2)^(2)) * 3");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn nth_root() {
+            let expected = Ok(Value::Number(2.));
+
+            let actual = calc_latex(r"\sqrt[4]{16}");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn single_character_arguments() {
+            let expected = Ok(Value::Number(0.5 * 4.));
+
+            let actual = calc_latex(r"\frac12 \cdot 2^2");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn functions_without_parentheses() {
+            let expected = Ok(Value::Number(1.));
+
+            let actual = calc_latex(r"\sin\frac{\pi}{2} \cdot \cos 0");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn greek_letters_are_names() {
+            let expected = Err(CalcError::new("Unknown name: alpha"));
+
+            let actual = calc_latex(r"2\tau + \alpha");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn delimiters_and_relations() {
+            let expected = Ok(Value::Bool(true));
+
+            let actual = calc_latex(r"\left|-3\right| + \lfloor 2.5 \rfloor \geq 5");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn common_logarithm() {
+            let expected = Ok(Value::Number(3.));
+
+            let actual = calc_latex(r"\log_{10}{1000}");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unknown_command() {
+            let expected = Err(CalcError::new(r"Unknown LaTeX command: \pm"));
+
+            let actual = calc_latex(r"1 \pm 2");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unclosed_brace() {
+            let expected = Err(CalcError::new("Expected '}' before end of expression"));
+
+            let actual = calc_latex(r"\frac{1}{2");
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod rpn_stack {
+        use super::*;
+        use crate::settings::NumberFormat;
+
+        fn stack_after(lines: &[&str]) -> Result<Vec<Value>, CalcError> {
+            let mut stack = rpn::Stack::default();
+            for line in lines {
+                stack.apply_line(line)?;
+            }
+            Ok(stack.values)
+        }
+
+        fn numbers(values: &[f64]) -> Result<Vec<Value>, CalcError> {
+            Ok(values.iter().map(|value| Value::Number(*value)).collect())
+        }
+
+        #[test]
+        fn operators_pop_two() {
+            let expected = numbers(&[14.]);
+
+            let actual = stack_after(&["3 4 +", "2 *"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn operand_order() {
+            let expected = numbers(&[-3., 0.5, 8.]);
+
+            let actual = stack_after(&["1 4 -", "1 2 /", "2 3 ^"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn swap_dup_drop() {
+            let expected = numbers(&[2., 1., 1.]);
+
+            let actual = stack_after(&["1 2 3 drop swap dup"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn roll_moves_bottom_to_top() {
+            let expected = numbers(&[2., 3., 1.]);
+
+            let actual = stack_after(&["1 2 3 roll"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn functions_and_constants() {
+            let expected = numbers(&[3., std::f64::consts::PI]);
+
+            let actual = stack_after(&["9 sqrt pi", "2 1 min drop"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn error_keeps_stack() {
+            let expected = (Err(CalcError::new("+ needs 2 value(s) but the stack has 1")), numbers(&[5.]));
+
+            let mut stack = rpn::Stack::default();
+            stack.apply_line("5").unwrap();
+            let result = stack.apply_line("1 + +");
+            let actual = (result, Ok(stack.values));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unknown_word() {
+            let expected = Err(CalcError::new("Unknown name: foo"));
+
+            let actual = stack_after(&["1 foo"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn render_levels() {
+            let expected = "2: 1.5\n1: true\n";
+
+            let stack = rpn::Stack { values: vec![Value::Number(1.5), Value::Bool(true)], ..rpn::Stack::default() };
+            let actual = stack.render(&NumberFormat::default());
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod convert_syntax {
+        use super::*;
+
+        fn convert_str(input: &str, from: Syntax, to: Syntax) -> Result<String, CalcError> {
+            let settings = Settings { syntax: from, ..Settings::default() };
+            convert::convert(input, &settings, to).1
+        }
+
+        #[test]
+        fn infix_to_rpn() {
+            let expected = Ok(String::from("2 3 4 + * x sqrt y neg / -"));
+
+            let actual = convert_str("2(3 + 4) - √x / -y", Syntax::Infix, Syntax::Rpn);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn infix_to_sexpr() {
+            let expected = Ok(String::from("(if (< x 2) (max 1 (^ 2 3)) (- x))"));
+
+            let actual = convert_str("x < 2 ? max(1, 2^3) : -x", Syntax::Infix, Syntax::Sexpr);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn rpn_to_infix() {
+            let expected = Ok(String::from("(3 + 4) * 2 - atan2(1, 2)"));
+
+            let actual = convert_str("3 4 + 2 * 1 2 atan2 -", Syntax::Rpn, Syntax::Infix);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn sexpr_to_infix() {
+            let expected = Ok(String::from("(1 + 2 + 3) * -x"));
+
+            let actual = convert_str("(* (+ 1 2 3) (- x))", Syntax::Sexpr, Syntax::Infix);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn round_trip_keeps_tree() {
+            let inputs = ["2(3 + 4)^2 - √x / -y", "1 < 2 ? 3 : 4 - 5", "2^3^2 / (1 - 4) - -3", "format(1, \"sig=2\")"];
+
+            for input in inputs {
+                for via in [Syntax::Rpn, Syntax::Sexpr] {
+                    let expected = parse_input(input, &Settings::default()).1;
+
+                    let converted = convert_str(input, Syntax::Infix, via).unwrap();
+                    let actual = parse_input(&converted, &Settings { syntax: via, ..Settings::default() }).1;
+
+                    assert_eq!(expected, actual);
+                }
+            }
+        }
+
+        #[test]
+        fn variadic_calls_round_trip() {
+            let variadic = crate::functions::FUNCTIONS.iter().filter(|function| function.variadic());
+
+            for function in variadic {
+                for args in ["1, 2", "1, 2, 3", "1, 2, 3, 4"] {
+                    let input = format!("{}({args})", function.name);
+                    let expected = parse_input(&input, &Settings::default()).1;
+
+                    let converted = convert_str(&input, Syntax::Infix, Syntax::Rpn).unwrap();
+                    let actual = parse_input(&converted, &Settings { syntax: Syntax::Rpn, ..Settings::default() }).1;
+
+                    assert_eq!(expected, actual);
+                }
+            }
+        }
+
+        #[test]
+        fn evaluate_rpn_and_sexpr() {
+            let expected = [Ok(Value::Number(14.)), Ok(Value::Number(14.))];
+
+            let actual = [
+                evaluate_input("3 4 + 2 *", &Settings { syntax: Syntax::Rpn, ..Settings::default() }).1,
+                evaluate_input("(* (+ 3 4) 2)", &Settings { syntax: Syntax::Sexpr, ..Settings::default() }).1,
+            ];
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn rpn_leftover_values() {
+            let expected = Err(CalcError::new("Expression leaves 2 values on the stack, expected 1"));
+
+            let actual = convert_str("1 2", Syntax::Rpn, Syntax::Infix);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn sexpr_operator_arity() {
+            let expected = Err(CalcError::new("(/ ...) takes 2 arguments but 3 were given"));
+
+            let actual = convert_str("(/ 1 2 3)", Syntax::Sexpr, Syntax::Infix);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn sexpr_unclosed() {
+            let expected = Err(CalcError::new("'(' is never closed, expected ')'"));
+
+            let actual = convert_str("(+ 1 (* 2 3)", Syntax::Sexpr, Syntax::Infix);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn parse_convert_command() {
+            let expected = cli::Command::Convert(Syntax::Sexpr);
+
+            let actual = cli::parse_args(["convert", "--to", "sexpr", "1"].iter().map(|arg| arg.to_string()), Settings::default()).unwrap().command;
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod spreadsheet {
+        use super::*;
+        use crate::settings::NumberFormat;
+
+        fn evaluate_formula(input: &str) -> Result<Value, CalcError> {
+            let scope = sheet::read_grid("1,2,3\n4,\"x\",6\n,8,9\n").unwrap();
+            let settings = Settings { syntax: Syntax::Sheet, scope, ..Settings::default() };
+            evaluate_input(input, &settings).1
+        }
+
+        #[test]
+        fn sum_of_range() {
+            let expected = Ok(Value::Number(10.));
+
+            let actual = evaluate_formula("=SUM(A1:A3)*2");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn case_insensitive_names() {
+            let expected = Ok(Value::Number(2.));
+
+            let actual = evaluate_formula("=average($a$1:C1)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn concat_and_not_equal() {
+            let expected = Ok(Value::Text(String::from("x!")));
+
+            let actual = evaluate_formula("=IF(A1 <> 1, 1/0, B2 & \"!\")");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn round_to_digits() {
+            let expected = Ok(Value::Number(1234.57));
+
+            let actual = evaluate_formula("ROUND(1234.5678, 2)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn spreadsheet_precedence() {
+            let expected = vec![Ok(Value::Number(4.)), Ok(Value::Number(64.)), Ok(Value::Number(2.))];
+
+            let actual = vec![evaluate_formula("=-2^2"), evaluate_formula("=2^3^2"), evaluate_formula("=50%*A2")];
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn spreadsheet_truth_values() {
+            let expected = vec![Ok(Value::Number(1.)), Ok(Value::Number(2.)), Ok(Value::Number(1.)), Ok(Value::Bool(true))];
+
+            let actual = vec![evaluate_formula("=IF(TRUE, 1, 2)"), evaluate_formula("=IF(A3, 1, 2)"), evaluate_formula("=IF(C3, 1, 2)"), evaluate_formula("=(A1 = 2) = FALSE()")];
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn cells_beyond_grid_are_empty() {
+            let expected = (Ok(Value::Number(1.)), vec![vec![String::from("1"), String::from("3")]]);
+
+            let actual = (evaluate_formula("=A1+B9"), evaluate_csv("1,=A1+B9+A1*2\n").0);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn function_name_ending_in_digits() {
+            let expected = Ok(Value::Number(3.));
+
+            let actual = evaluate_formula("=LOG10(100) + A1");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn doubled_quote_in_text() {
+            let expected = Ok(Value::Text(String::from("a\"b")));
+
+            let actual = evaluate_formula("=\"a\"\"b\"");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn range_beyond_limit() {
+            let expected = Err(CalcError::new("The range A1:ZZZ100000 covers 1827800000 cells, at most 100000 are supported"));
+
+            let actual = evaluate_formula("=SUM(A1:ZZZ100000)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn range_outside_function() {
+            let expected = Err(CalcError::new("The range A1:A3 can only be a function argument"));
+
+            let actual = evaluate_formula("=A1:A3 + 1");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn if_argument_count() {
+            let expected = Err(CalcError::new("IF takes 3 argument(s) but 2 were given"));
+
+            let actual = evaluate_formula("=IF(1, 2)");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn cell_names() {
+            let expected = (String::from("AB12"), Some((27, 11)));
+
+            let actual = (sheet::cell_at(27, 11), sheet::cell_position("AB12"));
+
+            assert_eq!(expected, actual);
+        }
+
+        fn evaluate_csv(text: &str) -> (Vec<Vec<String>>, Vec<(String, CalcError)>) {
+            sheet::evaluate_sheet(&sheet::read_csv(text).unwrap(), &NumberFormat::default())
+        }
+
+        #[test]
+        fn sheet_dependency_order() {
+            let expected = (vec![
+                vec![String::from("qty"), String::from("price"), String::from("total")],
+                vec![String::from("3"), String::from("2.5"), String::from("7.5")],
+                vec![String::from("5"), String::from(""), String::from("15.6")],
+            ], Vec::new());
+
+            let actual = evaluate_csv("qty,price,total\n3,2.5,=A2*B2\n=A2+2,,=C2*2+A2/A3\n");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn sheet_cycle() {
+            let cycle = CalcError::new("Circular reference: A1 -> B1 -> A1");
+            let expected = (vec![vec![String::from("#ERROR"), String::from("#ERROR"), String::from("#ERROR")]], vec![
+                (String::from("A1"), cycle.clone()),
+                (String::from("B1"), cycle),
+                (String::from("C1"), CalcError::new("Refers to A1, which failed")),
+            ]);
+
+            let actual = evaluate_csv("=B1+1,=A1*2,=A1\n");
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn sheet_large_range() {
+            let expected = vec![String::from("1250025000"), String::from("25000.5"), String::from("50000")];
+
+            let mut text: String = (1..=50000).map(|n| format!("{n}\n")).collect();
+            text.insert_str(0, "0,=SUM(A2:A50001),=AVERAGE(A2:A50001),=MAX(A1:A50001)\n");
+            let (rows, _) = evaluate_csv(&text);
+            let actual = rows[0][1..].to_vec();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn csv_quoted_fields() {
+            let expected = vec![vec![String::from("a, \"b\""), String::from("=SUM(A2, 1)")], vec![String::from("1")]];
+
+            let actual = sheet::read_csv("\"a, \"\"b\"\"\",\"=SUM(A2, 1)\"\r\n1").unwrap();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn parse_sheet_command() {
+            let expected = (cli::Command::Sheet, Some(String::from("data.csv")));
+
+            let args = cli::parse_args(["sheet", "--csv", "data.csv"].iter().map(|arg| arg.to_string()), Settings::default()).unwrap();
+            let actual = (args.command, args.csv);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod reactive {
+        use super::*;
+
+        #[test]
+        fn functions_update_callers() {
+            let expected = "f(x) = x ^ 2 + k\nk = 1\ny = f ( 3 ) = 10\nk = 2\ny = f ( 3 ) = 11\nf(x) = x * k\ny = f ( 3 ) = 6\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &["f(x) = x^2 + k", "k = 1", "y = f(3)", "k = 2", "f(x) = x * k"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn angle_unit_recomputes() {
+            let expected = "right = asin ( 1 ) = 1.5707963267948966\nangle deg\nright = 90\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &["right = asin(1)", ":set angle deg", "right"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn dependents_follow_changes() {
+            let expected = "area = w * h\nERROR: Unknown name: w\nw = 3\narea = w * h\nERROR: Unknown name: h\nh = 4\narea = w * h = 12\nw = 5\narea = w * h = 20\nw * h + area = 40\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &["area = w * h", "w = 3", "h = 4", "w = 5", "w * h + area"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn recompute_in_dependency_order() {
+            let expected = "a = 10\nb = a * 2 = 20\nc = a + b = 30\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            run_lines(&mut session, &["a = 1", "c = a + b", "b = a * 2"]);
+            let actual = run_lines(&mut session, &["a = 10"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn circular_definition() {
+            let expected = "ERROR: Circular definition: w -> area -> w\nERROR: Circular definition: x -> x\nw = 2\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            run_lines(&mut session, &["w = 2", "area = w * 3"]);
+            let actual = run_lines(&mut session, &["w = area", "x = x + 1", "w"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn failure_spreads() {
+            let expected = "w = 1 / 0\nERROR: Division by zero\narea = w * 2\nERROR: Refers to w, which failed\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            run_lines(&mut session, &["w = 1", "area = w * 2"]);
+            let actual = run_lines(&mut session, &["w = 1/0"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn deps_tree() {
+            let expected = "scaled = area / 2 = 6\n  area = w * h = 12\n    w = 3\n    h = 4\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            run_lines(&mut session, &["w = 3", "h = 4", "area = w * h", "scaled = area / 2"]);
+            let actual = run_lines(&mut session, &["deps scaled"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn comparisons_are_not_definitions() {
+            let expected = "2 == 2 = true\nERROR: pi is a constant and cannot be redefined\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &["2 == 2", "pi = 3"]);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod line_editor {
+        use crate::editor::{Key, Outcome, State};
+
+        fn type_keys(state: &mut State, keys: &[Key], history: &[String]) -> Outcome {
+            let mut outcome = Outcome::Continue;
+            for key in keys {
+                outcome = state.apply(*key, history);
+            }
+            outcome
+        }
+
+        fn chars(text: &str) -> Vec<Key> {
+            text.chars().map(Key::Char).collect()
+        }
+
+        #[test]
+        fn edit_in_the_middle() {
+            let expected = Outcome::Submit(String::from("2 * (3 + 4)"));
+
+            let mut state = State::new(&[]);
+            let keys = [chars("2 * 3 + 4)"), vec![Key::Home, Key::WordRight, Key::Right, Key::Right, Key::Right, Key::Char('('), Key::End, Key::Enter]].concat();
+            let actual = type_keys(&mut state, &keys, &[]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn kill_word_and_line() {
+            let expected = (String::from("sqrt("), String::new());
+
+            let mut state = State::new(&[]);
+            type_keys(&mut state, &[chars("sqrt(pi"), vec![Key::KillWord]].concat(), &[]);
+            let killed_word = state.line.text();
+            type_keys(&mut state, &[Key::KillStart], &[]);
+            let actual = (killed_word, state.line.text());
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn recall_history() {
+            let expected = vec![String::from("2 + 2"), String::from("1 + 1"), String::from("1 + 1"), String::from("2 + 2"), String::from("3")];
+
+            let history = [String::from("1 + 1"), String::from("2 + 2")];
+            let mut state = State::new(&history);
+            type_keys(&mut state, &chars("3"), &history);
+            let mut actual = Vec::<String>::new();
+            for key in [Key::Up, Key::Up, Key::Up, Key::Down, Key::Down] {
+                state.apply(key, &history);
+                actual.push(state.line.text());
+            }
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn reverse_search() {
+            let expected = (String::from("(reverse-i-search)`sq': sqrt(2)"), Outcome::Submit(String::from("sqrt(9)")));
+
+            let history = [String::from("sqrt(9)"), String::from("1 + 1"), String::from("sqrt(2)")];
+            let mut state = State::new(&history);
+            type_keys(&mut state, &[vec![Key::Search], chars("sq")].concat(), &history);
+            let shown = state.render("> ", &history, false).0;
+            let outcome = type_keys(&mut state, &[Key::Search, Key::Enter], &history);
+            let actual = (shown, outcome);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn search_then_edit() {
+            let expected = String::from("1 + 12");
+
+            let history = [String::from("1 + 1"), String::from("2 + 2")];
+            let mut state = State::new(&history);
+            type_keys(&mut state, &[vec![Key::Search], chars("1 +"), vec![Key::End, Key::Char('2')]].concat(), &history);
+            let actual = state.line.text();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn eof_only_on_empty_line() {
+            let expected = (Outcome::Continue, String::from("12"), Outcome::Eof);
+
+            let mut state = State::new(&[]);
+            let first = type_keys(&mut state, &[chars("123"), vec![Key::Left, Key::Eof]].concat(), &[]);
+            let text = state.line.text();
+            let last = type_keys(&mut state, &[Key::KillStart, Key::KillEnd, Key::Eof], &[]);
+            let actual = (first, text, last);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod tab_completion {
+        use super::*;
+        use crate::completion::{self, Candidate};
+        use crate::editor::{Key, State};
+        use crate::eval::Scope;
+        use crate::settings::NumberFormat;
+
+        fn complete_keys(text: &str, scope: &Scope) -> (String, Option<Vec<String>>) {
+            let mut state = State::new(&[]);
+            for c in text.chars() {
+                state.apply(Key::Char(c), &[]);
+            }
+            let (start, candidates) = completion::complete(&state.line.text(), state.line.cursor, scope, &NumberFormat::default());
+            let listing = state.complete(start, &candidates);
+            (state.line.text(), listing)
+        }
+
+        #[test]
+        fn candidates_from_registry() {
+            let expected = (4, vec![
+                Candidate { text: String::from("atan("), display: String::from("atan(x)") },
+                Candidate { text: String::from("atan2("), display: String::from("atan2(y, x)") },
+            ]);
+
+            let actual = completion::complete("2 * at", 6, &Scope::new(), &NumberFormat::default());
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn extends_common_prefix() {
+            let expected = (String::from("1 + atan"), None);
+
+            let actual = complete_keys("1 + at", &Scope::new());
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn lists_signatures_when_ambiguous() {
+            let expected = (String::from("atan"), Some(vec![String::from("atan(x)"), String::from("atan2(y, x)")]));
+
+            let actual = complete_keys("atan", &Scope::new());
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn single_function_opens_call() {
+            let expected = (String::from("sqrt("), None);
+
+            let actual = complete_keys("sq", &Scope::new());
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn variables_and_constants() {
+            let expected = (String::from("ta"), Some(vec![String::from("tan(x)"), String::from("tau = 6.283185307179586"), String::from("tax = 0.2")]));
+
+            let scope = Scope::from([(String::from("tax"), Value::Number(0.2))]);
+            let actual = complete_keys("ta", &scope);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn commands_after_colon() {
+            let expected = (String::from(":deps"), None);
+
+            let actual = complete_keys(":d", &Scope::new());
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod syntax_highlight {
+        use crate::highlight;
+
+        #[test]
+        fn token_kinds() {
+            let expected = "\x1b[36m2\x1b[0m \x1b[33m*\x1b[0m \x1b[34mpi\x1b[0m \x1b[33m&\x1b[0m \x1b[32m\"cm\"\x1b[0m";
+
+            let actual = highlight::highlight("2 * pi & \"cm\"", 0);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unicode_symbols_by_their_meaning() {
+            let expected = "\x1b[36m2\x1b[0m\x1b[33m×\x1b[0m\x1b[34mπ\x1b[0m";
+
+            let actual = highlight::highlight("2×π", 0);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn superscript_exponent() {
+            let expected = "\x1b[34mx\x1b[0m\x1b[36m²\x1b[0m\x1b[33m+\x1b[0m\x1b[36m2\x1b[0m\x1b[36m⁻\x1b[0m\x1b[36m¹\x1b[0m";
+
+            let actual = highlight::highlight("x²+2⁻¹", 0);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn mismatched_brackets() {
+            let expected = "\x1b[1;31m(\x1b[0m\x1b[36m1\x1b[0m\x1b[1;31m]\x1b[0m \x1b[1;31m$\x1b[0m";
+
+            let actual = highlight::highlight("(1] $", 0);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn matching_bracket_at_cursor() {
+            let expected = "\x1b[1;7m(\x1b[0m\x1b[34ma\x1b[0m\x1b[33m+\x1b[0m(\x1b[36m1\x1b[0m)\x1b[1;7m)\x1b[0m";
+
+            // Cursor just after the last bracket
+            let actual = highlight::highlight("(a+(1))", 7);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod meta_commands {
+        use super::*;
+
+        #[test]
+        fn vars_lists_definitions() {
+            let expected = "area = w * h = 6\nh = 3\nw = 2\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            run_lines(&mut session, &["w = 2", "h = 3", "area = w * h"]);
+            let actual = run_lines(&mut session, &[":vars"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn clear_forgets_definitions() {
+            let expected = "Cleared 1 definition(s)\nNo values defined\nERROR: Unknown name: w\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            run_lines(&mut session, &["w = 2"]);
+            let actual = run_lines(&mut session, &[":clear", ":vars", "w"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn help_for_function() {
+            let expected = "atan2(y, x)  Angle of the point (x, y)\n:set [NAME VALUE]  shows the settings, or changes one as in :set digits 3\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &[":help atan2", ":help set"]);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn set_and_reset() {
+            let expected = "digits 2\n1 / 3 = 0.33\nlocale de\n1.5 * 2 = 3,00\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &[":set digits 2", "1/3", ":set locale=de", "1,5 * 2"]);
+            run_lines(&mut session, &[":reset"]);
+
+            assert_eq!(expected, actual);
+            assert_eq!("1 / 3 = 0.3333333333333333\n", session.evaluate("1/3"));
+        }
+
+        #[test]
+        fn mode_switches_notation_and_output() {
+            let expected = "notation sci\n1200 = 1.2e3\nformat json\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &[":mode sci", "1200", ":mode json"]);
+
+            assert_eq!(expected, actual);
+            assert!(session.evaluate("1200").starts_with("{\"input\":\"1200\""));
+        }
+
+        #[test]
+        fn settings_round_trip() {
+            let expected = vec![Some(String::from("3")), Some(String::from("off")), Some(String::from("eng")), Some(String::from("ch"))];
+
+            let mut settings = Settings::default();
+            for (name, value) in [("sig", "3"), ("notation", "eng"), ("locale", "ch")] {
+                settings.set(name, value).unwrap();
+            }
+            let actual = vec![settings.get("sig"), settings.get("digits"), settings.get("notation"), settings.get("locale")];
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn unknown_command() {
+            let expected = "ERROR: Unknown command: :quit (see :help)\nERROR: Usage: :deps NAME\nERROR: Unknown setting: colour (expected notation, digits, sig, locale, syntax, implicit, angle, format)\n";
+
+            let mut session = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut session, &[":quit", ":deps", ":set colour red"]);
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod session_files {
+        use super::*;
+        use std::io::Cursor;
+
+        #[test]
+        fn script_lists_inputs_first() {
+            let expected = "\
+# Calculator session, replay with :load FILE or calculator -f FILE
+:set syntax infix
+:set locale c
+:set implicit standard
+:set angle rad
+w = 2
+area = w * w
+:set notation auto
+:set digits 3
+:set locale c
+:set syntax infix
+:set implicit standard
+:set angle rad
+:set format plain
+";
+
+            let mut session = repl::Session::new(Settings::default());
+            run_lines(&mut session, &["w = 2", "area = w * w", ":set digits 3"]);
+            let actual = session.script();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn save_and_load() {
+            let file = std::env::temp_dir().join(format!("calculator-session-{}.calc", std::process::id()));
+            let file = file.to_str().unwrap();
+            let expected = (format!("Loaded {file}\narea = w * w = 16\nw = 4\n"), vec![String::from("w = 4")]);
+
+            let mut saved = repl::Session::new(Settings::default());
+            run_lines(&mut saved, &["w = 4", "area = w * w"]);
+            saved.history.push(String::from("w = 4"));
+            run_lines(&mut saved, &[&format!(":save {file}")]);
+            let mut loaded = repl::Session::new(Settings::default());
+            let output = run_lines(&mut loaded, &[&format!(":load {file}"), ":vars"]);
+            let actual = (output, loaded.history.clone());
+            std::fs::remove_file(file).unwrap();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn functions_round_trip() {
+            let file = std::env::temp_dir().join(format!("calculator-functions-{}.calc", std::process::id()));
+            let file = file.to_str().unwrap();
+            let expected = format!("Loaded {file}\nf = hyp ( 3 , 4 ) = 5\nhyp ( 6 , 8 ) = 10\nhyp(a, b) = sqrt ( sq ( a ) + sq ( b ) )\n");
+
+            let mut saved = repl::Session::new(Settings::default());
+            run_lines(&mut saved, &["sq(x) = x * x", "hyp(a, b) = sqrt(sq(a) + sq(b))", "f = hyp(3, 4)"]);
+            run_lines(&mut saved, &[&format!(":save {file}")]);
+            let mut loaded = repl::Session::new(Settings::default());
+            let actual = run_lines(&mut loaded, &[&format!(":load {file}"), ":vars", "hyp(6, 8)", ":help hyp"]);
+            std::fs::remove_file(file).unwrap();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn batch_applies_definitions_and_settings() {
+            let input = "w = 1 / 3\n:set digits 2\nw * 3\nw\n:mode sci\n";
+            let expected = (String::from("1.00\n0.33\n"), String::from("exprs.txt:5: ERROR: Only :set applies in scripts, other commands are interactive: :mode sci\n"));
+
+            let mut session = repl::Session::new(Settings::default());
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            let mut summary = batch::Summary::default();
+            batch::evaluate_lines(Cursor::new(input), "exprs.txt", &mut session, true, &mut out, &mut err, &mut summary).unwrap();
+            let actual = (String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap());
+
+            assert_eq!(expected, actual);
+        }
+    }
+
+    mod config_file {
+        use super::*;
+        use std::path::{Path, PathBuf};
+
+        #[test]
+        fn keys_set_defaults() {
+            let text = "\
+# Defaults
+digits = 3   # after the point
+notation = \"eng\"
+locale = 'de'
+angle = \"deg\"
+backend = \"f64\"
+prelude = \"lib/constants.calc\"
+";
+            let expected = (Some(String::from("3")), Some(String::from("eng")), Some(String::from("de")), AngleUnit::Degrees, Some(PathBuf::from("/home/me/.config/calculator/lib/constants.calc")), Syntax::Infix);
+
+            let config = config::parse_config(text, Path::new("/home/me/.config/calculator")).unwrap();
+            let settings = &config.settings;
+            let actual = (settings.get("digits"), settings.get("notation"), settings.get("locale"), settings.angle, config.prelude, settings.syntax);
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn rejected_lines() {
+            let expected = vec![
+                Some((2, String::from("Unsupported backend: bigdecimal (only f64 is available)"))),
+                Some((1, String::from("Unknown angle unit: degrees (expected rad or deg)"))),
+                Some((1, String::from("Tables are not supported: [display]"))),
+                Some((1, String::from("Unknown key: colour (expected notation, digits, sig, locale, syntax, implicit, angle, format, backend, prelude)"))),
+                Some((2, String::from("Duplicate key: digits"))),
+                Some((1, String::from("Unterminated string: \"sci"))),
+            ];
+
+            let actual: Vec<_> = ["digits = 2\nbackend = \"bigdecimal\"", "angle = \"degrees\"", "[display]", "colour = 1", "digits = 2\ndigits = 3", "notation = \"sci"].iter()
+                .map(|text| config::parse_config(text, Path::new("")).err().map(|(line, error)| (line, error.to_string())))
+                .collect();
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn options_override_config() {
+            let expected = (Some(String::from("off")), Some(String::from("2")), Some(String::from("eng")));
+
+            let config = config::parse_config("digits = 3\nnotation = \"eng\"", Path::new("")).unwrap();
+            let args = cli::parse_args(["--sig", "2", "1"].iter().map(|arg| arg.to_string()), config.settings).unwrap();
+            let actual = (args.settings.get("digits"), args.settings.get("sig"), args.settings.get("notation"));
+
+            assert_eq!(expected, actual);
+        }
+
+        #[test]
+        fn prelude_defines_values() {
+            let file = std::env::temp_dir().join(format!("calculator-prelude-{}.calc", std::process::id()));
+            std::fs::write(&file, "# Constants\ng = 9.81\nhalf_g = g / 2\n").unwrap();
+            let expected = (true, String::from("half_g * 2 = 9.81\n"));
+
+            let mut session = repl::Session::new(Settings::default());
+            let succeeded = batch::run_prelude(&file, &mut session);
+            let actual = (succeeded, session.evaluate("half_g * 2"));
+            std::fs::remove_file(&file).unwrap();
+
+            assert_eq!(expected, actual);
+        }
+    }
+}
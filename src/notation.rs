@@ -0,0 +1,202 @@
+use crate::settings::{Notation, NumberFormat, Precision};
+
+// Decimal digits of a magnitude, value = 0.d1d2d3... * 10^point
+struct Decimal {
+    digits: Vec<u8>,
+    point: i32,
+}
+
+impl Decimal {
+    // Shortest digits that read back as the same f64
+    fn shortest(x: f64) -> Decimal {
+        Decimal::from_exponent_form(&format!("{:e}", x.abs()))
+    }
+
+    // Every digit of the binary value, so rounding ties are real ties
+    fn exact(x: f64) -> Decimal {
+        Decimal::from_exponent_form(&format!("{:.767e}", x.abs()))
+    }
+
+    fn from_exponent_form(text: &str) -> Decimal {
+        let (mantissa, exponent) = text.split_once('e').unwrap_or((text, "0"));
+        let mut digits: Vec<u8> = mantissa.bytes()
+            .filter(u8::is_ascii_digit)
+            .map(|b| b - b'0')
+            .collect();
+        let exponent: i32 = exponent.parse().unwrap_or(0);
+
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        Decimal { digits, point: exponent + 1 }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.digits.is_empty()
+    }
+
+    // Keep the first `keep` digits, rounding half away from zero
+    fn round(&mut self, keep: i32) {
+        if keep < 0 {
+            self.digits.clear();
+            return;
+        }
+        let keep = keep as usize;
+        if self.digits.len() <= keep {
+            return;
+        }
+
+        let round_up = self.digits[keep] >= 5;
+        self.digits.truncate(keep);
+        if round_up {
+            let mut i = keep;
+            loop {
+                // Carried past the first digit, 999 becomes 1000
+                if i == 0 {
+                    self.digits.insert(0, 1);
+                    self.point += 1;
+                    break;
+                }
+                i -= 1;
+                if self.digits[i] == 9 {
+                    self.digits[i] = 0;
+                }
+                else {
+                    self.digits[i] += 1;
+                    break;
+                }
+            }
+        }
+
+        while self.digits.last() == Some(&0) {
+            self.digits.pop();
+        }
+    }
+
+    fn digit(&self, index: i32) -> char {
+        let digit = usize::try_from(index).ok()
+            .and_then(|index| self.digits.get(index))
+            .copied()
+            .unwrap_or(0);
+        (b'0' + digit) as char
+    }
+
+    // Plain positional digits with at least min_decimals after the point
+    fn positional(&self, min_decimals: usize) -> String {
+        let mut output = String::new();
+
+        if self.point <= 0 {
+            output.push('0');
+        }
+        for i in 0..self.point {
+            output.push(self.digit(i));
+        }
+
+        let decimals = (self.digits.len() as i32 - self.point).max(min_decimals as i32);
+        if decimals > 0 {
+            output.push('.');
+            for i in self.point..self.point + decimals {
+                output.push(self.digit(i));
+            }
+        }
+
+        output
+    }
+
+    // Digits with the point after int_digits, followed by an exponent
+    fn with_exponent(&self, int_digits: i32, min_decimals: usize, exponent: i32) -> String {
+        let shifted = Decimal { digits: self.digits.clone(), point: int_digits };
+        format!("{}e{exponent}", shifted.positional(min_decimals))
+    }
+}
+
+pub(crate) fn format_number(x: f64, format: &NumberFormat) -> String {
+    if !x.is_finite() {
+        return x.to_string();
+    }
+
+    let mut decimal = match format.precision {
+        Precision::Shortest => Decimal::shortest(x),
+        _ => Decimal::exact(x),
+    };
+
+    let output = match format.notation {
+        Notation::Auto => format_positional(&mut decimal, format.precision),
+        Notation::Scientific => format_scientific(&mut decimal, format.precision),
+        Notation::Engineering => format_engineering(&mut decimal, format.precision),
+    };
+
+    // Rounding may leave nothing but zeros, which carry no sign
+    let output = if x.is_sign_negative() && !decimal.is_zero() {
+        format!("-{output}")
+    }
+    else {
+        output
+    };
+
+    format.locale.localize(&output)
+}
+
+fn format_positional(decimal: &mut Decimal, precision: Precision) -> String {
+    match precision {
+        Precision::Shortest => decimal.positional(0),
+        Precision::Decimals(decimals) => {
+            decimal.round(decimal.point + decimals as i32);
+            decimal.positional(decimals)
+        },
+        Precision::Significant(figures) => {
+            decimal.round(figures as i32);
+            // Trailing zeros within the figures are significant
+            let decimals = (figures as i32 - decimal.point).max(0) as usize;
+            decimal.positional(decimals)
+        },
+    }
+}
+
+fn format_scientific(decimal: &mut Decimal, precision: Precision) -> String {
+    let decimals = match precision {
+        Precision::Shortest => 0,
+        Precision::Decimals(decimals) => {
+            decimal.round(decimals as i32 + 1);
+            decimals
+        },
+        Precision::Significant(figures) => {
+            decimal.round(figures as i32);
+            figures.saturating_sub(1)
+        },
+    };
+
+    if decimal.is_zero() {
+        return Decimal { digits: Vec::new(), point: 1 }.with_exponent(1, decimals, 0);
+    }
+    decimal.with_exponent(1, decimals, decimal.point - 1)
+}
+
+fn format_engineering(decimal: &mut Decimal, precision: Precision) -> String {
+    // Exponent is a multiple of three with 1 to 3 digits before the point
+    let layout = |decimal: &Decimal| {
+        let exponent = (decimal.point - 1).div_euclid(3) * 3;
+        (exponent, decimal.point - exponent)
+    };
+
+    let decimals = match precision {
+        Precision::Shortest => 0,
+        Precision::Decimals(decimals) => {
+            let (_, int_digits) = layout(decimal);
+            decimal.round(int_digits + decimals as i32);
+            decimals
+        },
+        Precision::Significant(figures) => {
+            decimal.round(figures as i32);
+            let (_, int_digits) = layout(decimal);
+            (figures as i32 - int_digits).max(0) as usize
+        },
+    };
+
+    if decimal.is_zero() {
+        return Decimal { digits: Vec::new(), point: 1 }.with_exponent(1, decimals, 0);
+    }
+    let (exponent, int_digits) = layout(decimal);
+    decimal.with_exponent(int_digits, decimals, exponent)
+}
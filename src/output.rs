@@ -0,0 +1,147 @@
+use crate::error::CalcError;
+use crate::eval::Value;
+use crate::settings::{NumberFormat, OutputFormat};
+
+const COLUMNS: [&str; 7] = ["input", "expression", "result", "formatted", "type", "error", "warnings"];
+
+pub(crate) struct Record<'a> {
+    pub input: &'a str,
+    // Normalized expression as produced by format_tokens
+    pub expression: &'a str,
+    pub result: &'a Result<Value, CalcError>,
+    // Number format used for the formatted result
+    pub number: &'a NumberFormat,
+}
+
+impl Record<'_> {
+    fn result_type(&self) -> Option<&'static str> {
+        match self.result {
+            Ok(Value::Number(_)) => Some("number"),
+            Ok(Value::Bool(_)) => Some("boolean"),
+            Ok(Value::Text(_)) => Some("text"),
+            Err(_) => None,
+        }
+    }
+
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::<String>::new();
+        if let Ok(Value::Number(number)) = self.result && !number.is_finite() {
+            warnings.push(format!("Result is not a finite number: {number}"));
+        }
+        warnings
+    }
+}
+
+// Column names for formats that have a header row
+pub(crate) fn header(format: OutputFormat) -> Option<String> {
+    match format {
+        OutputFormat::Csv => Some(COLUMNS.join(",")),
+        OutputFormat::Tsv => Some(COLUMNS.join("\t")),
+        OutputFormat::Plain | OutputFormat::Json => None,
+    }
+}
+
+pub(crate) fn render(format: OutputFormat, record: &Record) -> String {
+    match format {
+        OutputFormat::Plain => render_plain(record),
+        OutputFormat::Json => render_json(record),
+        OutputFormat::Csv => render_fields(record).iter().map(|field| csv_field(field)).collect::<Vec<String>>().join(","),
+        OutputFormat::Tsv => render_fields(record).iter().map(|field| tsv_field(field)).collect::<Vec<String>>().join("\t"),
+    }
+}
+
+fn render_plain(record: &Record) -> String {
+    match record.result {
+        Ok(value) => value.display(record.number),
+        Err(error) => format!("ERROR: {error}"),
+    }
+}
+
+fn render_json(record: &Record) -> String {
+    let result = match record.result {
+        Ok(Value::Number(number)) if number.is_finite() => number.to_string(),
+        Ok(Value::Bool(value)) => value.to_string(),
+        Ok(Value::Text(text)) => json_string(text),
+        _ => String::from("null"),
+    };
+    let formatted = match record.result {
+        Ok(value) => json_string(&value.display(record.number)),
+        Err(_) => String::from("null"),
+    };
+    let result_type = record.result_type()
+        .map(json_string)
+        .unwrap_or_else(|| String::from("null"));
+    let error = match record.result {
+        Ok(_) => String::from("null"),
+        Err(error) => {
+            // Columns are 1 based for editors and tools
+            let column = error.column(record.expression)
+                .map(|column| (column + 1).to_string())
+                .unwrap_or_else(|| String::from("null"));
+            format!("{{\"message\":{},\"column\":{column}}}", json_string(&error.message))
+        },
+    };
+    let warnings = record.warnings().iter()
+        .map(|warning| json_string(warning))
+        .collect::<Vec<String>>()
+        .join(",");
+
+    format!(
+        "{{\"input\":{},\"expression\":{},\"result\":{result},\"formatted\":{formatted},\"type\":{result_type},\"error\":{error},\"warnings\":[{warnings}]}}",
+        json_string(record.input),
+        json_string(record.expression),
+    )
+}
+
+// Values for the header columns, in order
+fn render_fields(record: &Record) -> Vec<String> {
+    let (result, formatted, error) = match record.result {
+        Ok(value) => (value.to_string(), value.display(record.number), String::new()),
+        Err(error) => (String::new(), String::new(), error.message.clone()),
+    };
+
+    vec![
+        record.input.to_string(),
+        record.expression.to_string(),
+        result,
+        formatted,
+        record.result_type().unwrap_or("").to_string(),
+        error,
+        record.warnings().join("; "),
+    ]
+}
+
+fn json_string(text: &str) -> String {
+    let mut output = String::with_capacity(text.len() + 2);
+    output.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+pub(crate) fn csv_field(field: &str) -> String {
+    // Quote only when needed, doubling any quotes inside
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else {
+        field.to_string()
+    }
+}
+
+fn tsv_field(field: &str) -> String {
+    field.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
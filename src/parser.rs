@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+
+use crate::error::CalcError;
+use crate::functions::{self, Defined, Function, UserFunction};
+use crate::settings::{ImplicitMul, Settings};
+use crate::token::{Token, TokenType, TokenPrio};
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expr {
+    Number(f64),
+    // Quoted text without its quotes
+    Text(String),
+    // Truth value, written true or false
+    Bool(bool),
+    // Named constant such as pi
+    Name(String),
+    // Function name with its arguments
+    Call(String, Vec<Expr>),
+    // Unary minus applied to a sub expression
+    Negate(Box<Expr>),
+    // Operator token type with left and right operands
+    Binary(TokenType, Box<Expr>, Box<Expr>),
+    // Condition, then branch, else branch
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    // Calls f on this node, then on every node below it
+    pub fn visit(&self, f: &mut impl FnMut(&Expr)) {
+        f(self);
+        match self {
+            Expr::Call(_, args) => args.iter().for_each(|arg| arg.visit(f)),
+            Expr::Negate(operand) => operand.visit(f),
+            Expr::Binary(_, lhs, rhs) => {
+                lhs.visit(f);
+                rhs.visit(f);
+            },
+            Expr::Conditional(condition, then, otherwise) => {
+                condition.visit(f);
+                then.visit(f);
+                otherwise.visit(f);
+            },
+            Expr::Number(_) | Expr::Text(_) | Expr::Bool(_) | Expr::Name(_) => {},
+        }
+    }
+
+    // Names the expression refers to, in order of appearance
+    pub fn names(&self) -> Vec<String> {
+        let mut names = Vec::<String>::new();
+        let mut seen = HashSet::<String>::new();
+        self.visit(&mut |expr| {
+            if let Expr::Name(name) = expr && seen.insert(name.clone()) {
+                names.push(name.clone());
+            }
+        });
+        names
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    implicit: ImplicitMul,
+    // Functions defined in the session, their calls are kept as calls
+    defined: &'a Defined,
+    // Whether a '|' following an operand closes an absolute value
+    in_bars: bool,
+}
+
+pub(crate) fn parse(tokens: &[Token], settings: &Settings) -> Result<Expr, CalcError> {
+    if tokens.is_empty() {
+        return Err(CalcError::new("Empty expression"));
+    }
+
+    let mut parser = Parser { tokens, pos: 0, implicit: settings.implicit, defined: &settings.functions, in_bars: false };
+    let expr = parser.parse_expr(TokenPrio::COND)?;
+
+    // Everything must be consumed by the top level expression
+    match parser.peek() {
+        Some(token) if closes_group(token.ttype) => Err(CalcError::at(parser.pos, format!("Unmatched '{}'", token.value))),
+        Some(token) => Err(CalcError::at(parser.pos, format!("Unexpected token: {}", token.value))),
+        None => Ok(expr),
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&'a Token, CalcError> {
+        let token = self.tokens.get(self.pos)
+            .ok_or_else(|| CalcError::at(self.pos, "Unexpected end of expression"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, ttype: TokenType, expected: &str) -> Result<(), CalcError> {
+        match self.peek() {
+            Some(token) if token.ttype == ttype => {
+                self.pos += 1;
+                Ok(())
+            },
+            Some(token) => Err(CalcError::at(self.pos, format!("Expected '{expected}' but found '{}'", token.value))),
+            None => Err(CalcError::at(self.pos, format!("Expected '{expected}' before end of expression"))),
+        }
+    }
+
+    // Closing delimiter for the group opened by the token at open_pos
+    fn expect_close(&mut self, open_pos: usize, close: TokenType) -> Result<(), CalcError> {
+        let open = &self.tokens[open_pos].value;
+        let expected = closing_symbol(close);
+
+        match self.peek() {
+            Some(token) if token.ttype == close => {
+                self.pos += 1;
+                Ok(())
+            },
+            Some(token) if closes_group(token.ttype) => Err(CalcError::at(self.pos, format!("'{}' cannot close '{open}', expected '{expected}'", token.value))),
+            Some(token) => Err(CalcError::at(self.pos, format!("Expected '{expected}' to close '{open}' but found '{}'", token.value))),
+            None => Err(CalcError::at(open_pos, format!("'{open}' is never closed, expected '{expected}'"))),
+        }
+    }
+
+    // Contents of a delimited group, bars inside it are independent of bars outside
+    fn parse_group(&mut self, open_pos: usize, close: TokenType) -> Result<Expr, CalcError> {
+        let in_bars = self.in_bars;
+        self.in_bars = close == TokenType::Bar;
+        let inner = self.parse_expr(TokenPrio::COND);
+        self.in_bars = in_bars;
+
+        let inner = inner?;
+        self.expect_close(open_pos, close)?;
+        Ok(inner)
+    }
+
+    // Precedence climbing, only operators at or above min_prio are consumed
+    fn parse_expr(&mut self, min_prio: i8) -> Result<Expr, CalcError> {
+        let mut lhs = self.parse_prefix()?;
+
+        while let Some(token) = self.peek() {
+            // Juxtaposed operands such as 2pi or 3(4 + 5) are multiplied
+            if self.is_juxtaposed()? {
+                let prio = match self.implicit {
+                    ImplicitMul::Standard => TokenPrio::MUL,
+                    ImplicitMul::Tight => TokenPrio::IMP,
+                };
+                if prio < min_prio {
+                    break;
+                }
+                let rhs = self.parse_expr(prio + 1)?;
+                lhs = Expr::Binary(TokenType::Multiply, Box::new(lhs), Box::new(rhs));
+                continue;
+            }
+
+            if !is_infix(token.ttype) || token.prio < min_prio {
+                break;
+            }
+            let ttype = token.ttype;
+            let prio = token.prio;
+            self.pos += 1;
+
+            // Ternary conditional, right associative
+            if ttype == TokenType::Question {
+                let then = self.parse_expr(TokenPrio::COND)?;
+                self.expect(TokenType::Colon, ":")?;
+                let otherwise = self.parse_expr(TokenPrio::COND)?;
+                lhs = Expr::Conditional(Box::new(lhs), Box::new(then), Box::new(otherwise));
+                continue;
+            }
+
+            // Exponents are right associative, everything else left associative
+            let next_prio = if ttype == TokenType::Exponent { prio } else { prio + 1 };
+            let rhs = self.parse_expr(next_prio)?;
+            lhs = Expr::Binary(ttype, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_prefix(&mut self) -> Result<Expr, CalcError> {
+        let position = self.pos;
+        let token = self.next()?;
+
+        match token.ttype {
+            TokenType::Number => {
+                token.value.parse::<f64>()
+                    .map(Expr::Number)
+                    .map_err(|_| CalcError::at(position, format!("Could not parse number: {}", token.value)))
+            },
+            TokenType::Text => {
+                let text = &token.value;
+                Ok(Expr::Text(text[1..text.len() - 1].to_string()))
+            },
+            TokenType::Identifier => {
+                let is_call = self.peek().is_some_and(|next| next.ttype == TokenType::ParenOpen);
+                let defined = self.defined.get(&token.value);
+                match (functions::find_function(&token.value), is_call) {
+                    (Some(function), true) => self.parse_call(function, position),
+                    (Some(function), false) => Err(CalcError::at(position, format!("Function {} must be called with parentheses, such as {}", function.name, function.signature()))),
+                    (None, true) => match defined {
+                        Some(function) => self.parse_defined_call(function, position),
+                        None => Err(CalcError::at(position, format!("'{}' is not a function, use '*' to multiply", token.value))),
+                    },
+                    (None, false) if let Some(function) = defined => {
+                        Err(CalcError::at(position, format!("Function {} must be called with parentheses, such as {}", function.name, function.signature())))
+                    },
+                    (None, false) => Ok(match token.value.as_str() {
+                        "true" => Expr::Bool(true),
+                        "false" => Expr::Bool(false),
+                        _ => Expr::Name(token.value.clone()),
+                    }),
+                }
+            },
+            TokenType::Root => {
+                // Prefix root binds like unary minus, so √x² is √(x²)
+                let operand = self.parse_expr(TokenPrio::EXP)?;
+                Ok(Expr::Call(String::from("sqrt"), vec![operand]))
+            },
+            TokenType::Subtract => {
+                // Binds tighter than everything except exponents, so -x^2 is -(x^2).
+                // A sign written before digits is part of the number instead, so
+                // -2^2 is (-2)^2
+                let operand = self.parse_expr(TokenPrio::EXP)?;
+                Ok(Expr::Negate(Box::new(operand)))
+            },
+            TokenType::ParenOpen => self.parse_group(position, TokenType::ParenClose),
+            TokenType::BracketOpen => self.parse_group(position, TokenType::BracketClose),
+            TokenType::BraceOpen => self.parse_group(position, TokenType::BraceClose),
+            TokenType::Bar => {
+                let inner = self.parse_group(position, TokenType::Bar)?;
+                Ok(Expr::Call(String::from("abs"), vec![inner]))
+            },
+            TokenType::FloorOpen => {
+                let inner = self.parse_group(position, TokenType::FloorClose)?;
+                Ok(Expr::Call(String::from("floor"), vec![inner]))
+            },
+            TokenType::CeilOpen => {
+                let inner = self.parse_group(position, TokenType::CeilClose)?;
+                Ok(Expr::Call(String::from("ceil"), vec![inner]))
+            },
+            TokenType::If => {
+                let condition = self.parse_expr(TokenPrio::COND)?;
+                self.expect(TokenType::Then, "then")?;
+                let then = self.parse_expr(TokenPrio::COND)?;
+                self.expect(TokenType::Else, "else")?;
+                let otherwise = self.parse_expr(TokenPrio::COND)?;
+                Ok(Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+            },
+            _ => Err(CalcError::at(position, format!("Unexpected token: {}", token.value))),
+        }
+    }
+
+    fn parse_call(&mut self, function: &Function, position: usize) -> Result<Expr, CalcError> {
+        let args = self.parse_args()?;
+        if !function.accepts(args.len()) {
+            return Err(CalcError::at(position, format!("{} takes {} argument(s) but {} were given", function.signature(), function.takes(), args.len())));
+        }
+
+        Ok(Expr::Call(function.name.to_string(), args))
+    }
+
+    fn parse_defined_call(&mut self, function: &UserFunction, position: usize) -> Result<Expr, CalcError> {
+        let args = self.parse_args()?;
+        if args.len() != function.params.len() {
+            return Err(CalcError::at(position, format!("{} takes {} argument(s) but {} were given", function.signature(), function.params.len(), args.len())));
+        }
+
+        Ok(Expr::Call(function.name.clone(), args))
+    }
+
+    // Comma separated arguments in parentheses
+    fn parse_args(&mut self) -> Result<Vec<Expr>, CalcError> {
+        let open_pos = self.pos;
+        self.expect(TokenType::ParenOpen, "(")?;
+
+        // Comma separated arguments up to the closing paren
+        let mut args = Vec::<Expr>::new();
+        if self.peek().is_some_and(|token| token.ttype == TokenType::ParenClose) {
+            self.pos += 1;
+        }
+        else {
+            let in_bars = self.in_bars;
+            self.in_bars = false;
+            loop {
+                args.push(self.parse_expr(TokenPrio::COND)?);
+                if self.peek().is_some_and(|token| token.ttype == TokenType::Comma) {
+                    self.pos += 1;
+                    continue;
+                }
+                break;
+            }
+            self.in_bars = in_bars;
+            self.expect_close(open_pos, TokenType::ParenClose)?;
+        }
+
+        Ok(args)
+    }
+
+    // Whether the next token is an operand directly following the previous one
+    fn is_juxtaposed(&self) -> Result<bool, CalcError> {
+        let Some(next) = self.peek() else {
+            return Ok(false);
+        };
+        if !matches!(next.ttype, TokenType::Number | TokenType::Identifier | TokenType::Root) && !opens_group(next.ttype) {
+            return Ok(false);
+        }
+
+        // Inside absolute value bars, a bar after an operand is the closing one
+        if next.ttype == TokenType::Bar && self.in_bars {
+            return Ok(false);
+        }
+
+        // Only pairings that cannot be read as anything else
+        let prev = &self.tokens[self.pos - 1];
+        if closes_group(prev.ttype) {
+            return Ok(true);
+        }
+        match (prev.ttype, next.ttype) {
+            (TokenType::Number, TokenType::Number) => Err(CalcError::at(self.pos, format!("Missing operator between '{}' and '{}'", prev.value, next.value))),
+            (TokenType::Number, _) |
+            (TokenType::Identifier, TokenType::Identifier) |
+            (TokenType::Identifier, TokenType::Root) => Ok(true),
+            (TokenType::Identifier, ttype) if opens_group(ttype) => Ok(true),
+            _ => Err(CalcError::at(self.pos, format!("Missing operator between '{}' and '{}'", prev.value, next.value))),
+        }
+    }
+}
+
+fn opens_group(ttype: TokenType) -> bool {
+    matches!(ttype,
+        TokenType::ParenOpen |
+        TokenType::BracketOpen |
+        TokenType::BraceOpen |
+        TokenType::Bar |
+        TokenType::FloorOpen |
+        TokenType::CeilOpen
+    )
+}
+
+fn closes_group(ttype: TokenType) -> bool {
+    matches!(ttype,
+        TokenType::ParenClose |
+        TokenType::BracketClose |
+        TokenType::BraceClose |
+        TokenType::Bar |
+        TokenType::FloorClose |
+        TokenType::CeilClose
+    )
+}
+
+fn closing_symbol(ttype: TokenType) -> &'static str {
+    match ttype {
+        TokenType::BracketClose => "]",
+        TokenType::BraceClose => "}",
+        TokenType::Bar => "|",
+        TokenType::FloorClose => "⌋",
+        TokenType::CeilClose => "⌉",
+        _ => ")",
+    }
+}
+
+fn is_infix(ttype: TokenType) -> bool {
+    matches!(ttype,
+        TokenType::Addition |
+        TokenType::Subtract |
+        TokenType::Multiply |
+        TokenType::Division |
+        TokenType::Exponent |
+        TokenType::Equal |
+        TokenType::NotEqual |
+        TokenType::Less |
+        TokenType::LessEqual |
+        TokenType::Greater |
+        TokenType::GreaterEqual |
+        TokenType::Concat |
+        TokenType::Question
+    )
+}
@@ -0,0 +1,110 @@
+use crate::notation;
+use crate::parser::Expr;
+use crate::settings::{NumberFormat, Style};
+use crate::token::{TokenType, TokenPrio};
+
+// Expression written back as input, with only the parentheses it needs
+pub(crate) fn print(expr: &Expr) -> String {
+    print_styled(expr, &Style::default())
+}
+
+pub(crate) fn print_styled(expr: &Expr, style: &Style) -> String {
+    match expr {
+        Expr::Number(number) => notation::format_number(*number, &NumberFormat::default()),
+        Expr::Text(text) => format!("\"{text}\""),
+        Expr::Bool(value) => value.to_string(),
+        Expr::Name(name) => name.clone(),
+        Expr::Call(name, args) => {
+            let args: Vec<String> = args.iter().map(|arg| print_styled(arg, style)).collect();
+            let separator = if style.spaces { ", " } else { "," };
+            format!("{name}({})", args.join(separator))
+        },
+        Expr::Negate(operand) => {
+            // A leading digit or sign would be read back as a negative number, -(2^2) is
+            // not -2^2, only a plain number can lose its parentheses
+            let inner = print_styled(operand, style);
+            if matches!(**operand, Expr::Number(number) if number >= 0.) {
+                format!("-{inner}")
+            }
+            else if prio(operand) < TokenPrio::EXP || inner.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+                format!("-({inner})")
+            }
+            else {
+                format!("-{inner}")
+            }
+        },
+        Expr::Binary(ttype, lhs, rhs) => {
+            let op_prio = prio(expr);
+            // Exponents group to the right, everything else to the left
+            let (lhs_min, rhs_min) = if *ttype == TokenType::Exponent {
+                (op_prio + 1, op_prio)
+            }
+            else {
+                (op_prio, op_prio + 1)
+            };
+            let symbol = match ttype {
+                TokenType::Multiply => style.times.to_string(),
+                _ => symbol(*ttype).to_string(),
+            };
+            infix(style, &[operand(lhs, lhs_min, style), symbol, operand(rhs, rhs_min, style)])
+        },
+        Expr::Conditional(condition, then, otherwise) => {
+            infix(style, &[
+                operand(condition, TokenPrio::CMP, style),
+                String::from("?"),
+                operand(then, TokenPrio::CMP, style),
+                String::from(":"),
+                print_styled(otherwise, style),
+            ])
+        },
+    }
+}
+
+fn infix(style: &Style, parts: &[String]) -> String {
+    parts.join(if style.spaces { " " } else { "" })
+}
+
+// Operand that needs parentheses when it binds looser than min_prio
+fn operand(expr: &Expr, min_prio: i8, style: &Style) -> String {
+    if prio(expr) < min_prio {
+        format!("({})", print_styled(expr, style))
+    }
+    else {
+        print_styled(expr, style)
+    }
+}
+
+pub(crate) fn prio(expr: &Expr) -> i8 {
+    match expr {
+        Expr::Negate(_) => TokenPrio::EXP,
+        Expr::Binary(ttype, _, _) => match ttype {
+            TokenType::Addition => TokenPrio::ADD,
+            TokenType::Subtract => TokenPrio::SUB,
+            TokenType::Multiply => TokenPrio::MUL,
+            TokenType::Division => TokenPrio::DIV,
+            TokenType::Exponent => TokenPrio::EXP,
+            TokenType::Concat => TokenPrio::CAT,
+            _ => TokenPrio::CMP,
+        },
+        Expr::Conditional(..) => TokenPrio::COND,
+        _ => TokenPrio::PAR,
+    }
+}
+
+pub(crate) fn symbol(ttype: TokenType) -> &'static str {
+    match ttype {
+        TokenType::Addition => "+",
+        TokenType::Subtract => "-",
+        TokenType::Multiply => "*",
+        TokenType::Division => "/",
+        TokenType::Exponent => "^",
+        TokenType::Equal => "==",
+        TokenType::NotEqual => "!=",
+        TokenType::Less => "<",
+        TokenType::LessEqual => "<=",
+        TokenType::Greater => ">",
+        TokenType::GreaterEqual => ">=",
+        TokenType::Concat => "&",
+        _ => "?",
+    }
+}
@@ -0,0 +1,691 @@
+use std::collections::BTreeMap;
+
+use crate::cli::Args;
+use crate::completion;
+use crate::editor::Editor;
+use crate::error::CalcError;
+use crate::eval::{self, Value};
+use crate::functions::{self, UserFunction};
+use crate::output::{self, Record};
+use crate::parser::Expr;
+use crate::settings::{Notation, OutputFormat, SETTING_NAMES, Settings, Syntax};
+use crate::{error_pointer, error_report, evaluate_input, parse_input, print_explained, print_latex};
+
+const INTRO: &str = "\
+Enter an expression to evaluate it, name = expression to define a value, or
+f(x) = expression to define a function. Definitions keep their formula, so
+after area = w * h changing w updates area.
+";
+
+const KEYS: &str = "  Up, Down            recall earlier lines, Ctrl-R searches them
+  Tab                 completes names and commands
+  Ctrl-D              quits
+";
+
+// Commands with their arguments and what they do, for help and completion
+pub(crate) const COMMANDS: &[(&str, &str, &str)] = &[
+    (":help",  "[NAME]",       "lists commands, or describes a function, constant or value"),
+    (":vars",  "",             "lists defined values"),
+    (":funcs", "",             "lists functions with their parameters, defined ones last"),
+    (":deps",  "NAME",         "shows the values NAME is computed from"),
+    (":clear", "",             "forgets every definition"),
+    (":mode",  "[MODE]",       "switches notation (auto, sci, eng) or output (plain, json, csv, tsv)"),
+    (":set",   "[NAME VALUE]", "shows the settings, or changes one as in :set digits 3"),
+    (":reset", "",             "restores the settings the session started with"),
+    (":save",  "FILE",         "writes definitions, settings and history to FILE as a script"),
+    (":load",  "FILE",         "runs the lines of FILE, such as a script written by :save"),
+];
+
+// History lines in saved scripts, comments so that replaying leaves them out
+const HISTORY_PREFIX: &str = "# > ";
+
+// Named value kept as its formula, recomputed whenever a name it refers to changes
+struct Definition {
+    // Formula in plain syntax
+    formula: String,
+    expr: Expr,
+    value: Result<Value, CalcError>,
+}
+
+// State of an interactive session, the values of definitions live in the scope
+// of the settings so expressions can refer to them
+pub(crate) struct Session {
+    pub settings: Settings,
+    // Settings the session started with, for :reset
+    initial: Settings,
+    definitions: BTreeMap<String, Definition>,
+    // Lines entered so far, for :save
+    pub history: Vec<String>,
+}
+
+impl Session {
+    pub fn new(settings: Settings) -> Session {
+        Session { initial: settings.clone(), settings, definitions: BTreeMap::new(), history: Vec::new() }
+    }
+
+    // Output of a definition or command, None for lines that are expressions
+    pub fn command(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        if let Some(command) = line.strip_prefix(':') {
+            let (command, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            return Some(self.run_command(command, argument.trim()));
+        }
+        // deps without the colon, as first written
+        if let Some(name) = line.strip_prefix("deps ") {
+            return Some(self.deps(name.trim()));
+        }
+        if let Some((name, params, formula)) = split_function(line) {
+            return Some(self.define_function(name, &params, formula));
+        }
+        let (name, formula) = split_definition(line)?;
+        Some(self.define(name, formula))
+    }
+
+    pub fn evaluate(&self, input: &str) -> String {
+        let (formatted, result) = evaluate_input(input, &self.settings);
+        match (&result, self.settings.format) {
+            (Ok(value), OutputFormat::Plain) => format!("{formatted} = {}\n", value.display(&self.settings.number)),
+            (Err(error), OutputFormat::Plain) => error_report(&formatted, error),
+            (_, format) => {
+                let record = Record { input: input.trim(), expression: &formatted, result: &result, number: &self.settings.number };
+                format!("{}\n", output::render(format, &record))
+            },
+        }
+    }
+
+    fn run_command(&mut self, command: &str, argument: &str) -> String {
+        match (command, argument) {
+            ("help", "") => help(),
+            ("help", name) => self.describe_name(name),
+            ("vars", "") => self.vars(),
+            ("funcs", "") => self.funcs(),
+            ("deps", name) if !name.is_empty() => self.deps(name),
+            ("clear", "") => self.clear(),
+            ("mode" | "set", "") => self.show_settings(),
+            ("mode", mode) => self.mode(mode),
+            ("set", setting) => self.set(setting),
+            ("save", file) if !file.is_empty() => self.save(file),
+            ("load", file) if !file.is_empty() => self.load(file),
+            ("reset", "") => {
+                let scope = std::mem::take(&mut self.settings.scope);
+                let functions = std::mem::take(&mut self.settings.functions);
+                self.settings = Settings { scope, functions, ..self.initial.clone() };
+                self.show_settings()
+            },
+            _ => match COMMANDS.iter().find(|(name, ..)| name[1..] == *command) {
+                Some((name, usage, _)) => format!("ERROR: Usage: {}\n", format!("{name} {usage}").trim_end()),
+                None => format!("ERROR: Unknown command: :{command} (see :help)\n"),
+            },
+        }
+    }
+
+    // What a name stands for, be it a command, function, constant or value
+    fn describe_name(&self, name: &str) -> String {
+        let name = name.trim_end_matches('(');
+        let command = format!(":{}", name.trim_start_matches(':'));
+        if let Some((command, usage, about)) = COMMANDS.iter().find(|(other, ..)| *other == command) {
+            return format!("{}  {about}\n", format!("{command} {usage}").trim_end());
+        }
+        if let Some(function) = functions::find_function(name) {
+            return format!("{}  {}\n", function.signature(), function.summary);
+        }
+        if self.definitions.contains_key(name) || self.settings.functions.contains_key(name) || self.settings.scope.contains_key(name) || functions::find_constant(name).is_some() {
+            return format!("{}\n", self.summary(name));
+        }
+        format!("ERROR: Unknown name: {name}\n")
+    }
+
+    fn vars(&self) -> String {
+        let mut output: String = self.definitions.keys()
+            .map(|name| format!("{}\n", self.summary(name)))
+            .collect();
+
+        // Values that come with the settings, such as the cells of a grid
+        let mut others: Vec<&String> = self.settings.scope.keys()
+            .filter(|name| !self.definitions.contains_key(*name))
+            .collect();
+        others.sort();
+        for name in others {
+            output.push_str(&format!("{}\n", self.summary(name)));
+        }
+
+        if output.is_empty() {
+            return String::from("No values defined\n");
+        }
+        output
+    }
+
+    fn clear(&mut self) -> String {
+        for name in self.definitions.keys() {
+            self.settings.scope.remove(name);
+        }
+        let count = std::mem::take(&mut self.definitions).len() + std::mem::take(&mut self.settings.functions).len();
+        format!("Cleared {count} definition(s)\n")
+    }
+
+    fn show_settings(&self) -> String {
+        SETTING_NAMES.iter()
+            .map(|(name, values)| format!("{name:<10}{:<10}{values}\n", self.settings.get(name).unwrap_or_default()))
+            .collect()
+    }
+
+    fn mode(&mut self, mode: &str) -> String {
+        if let Ok(notation) = Notation::from_name(mode) {
+            self.settings.number.notation = notation;
+            return format!("notation {mode}\n");
+        }
+        match OutputFormat::from_name(mode) {
+            Ok(format) => {
+                self.settings.format = format;
+                // Rows of csv and tsv follow their column names
+                match output::header(format) {
+                    Some(header) => format!("format {mode}\n{header}\n"),
+                    None => format!("format {mode}\n"),
+                }
+            },
+            Err(_) => format!("ERROR: Unknown mode: {mode} (expected auto, sci, eng, plain, json, csv or tsv)\n"),
+        }
+    }
+
+    fn set(&mut self, setting: &str) -> String {
+        let (name, value) = split_setting(setting);
+        if value.is_empty() {
+            return match self.settings.get(name) {
+                Some(current) => format!("{name} {current}\n"),
+                None => String::from("ERROR: Usage: :set [NAME VALUE]\n"),
+            };
+        }
+
+        match self.change_setting(name, value) {
+            Ok(()) => format!("{name} {}\n", self.settings.get(name).unwrap_or_default()),
+            Err(error) => format!("ERROR: {error}\n"),
+        }
+    }
+
+    // Definitions are recomputed when the angle unit changes their values
+    fn change_setting(&mut self, name: &str, value: &str) -> Result<(), CalcError> {
+        let angle = self.settings.angle;
+        self.settings.set(name, value)?;
+        if self.settings.angle != angle {
+            let mut ordered = Vec::<String>::new();
+            for name in self.definitions.keys() {
+                self.inputs_first(name, &mut ordered);
+            }
+            for name in &ordered {
+                self.recompute(name);
+            }
+        }
+        Ok(())
+    }
+
+    // Output of a definition, followed by everything recomputed because of it
+    fn define(&mut self, name: &str, formula: &str) -> String {
+        match self.store(name, formula) {
+            Ok(changed) => changed.iter().map(|changed| self.describe(changed)).collect(),
+            Err((formatted, error)) if formatted.is_empty() => format!("ERROR: {error}\n"),
+            Err((formatted, error)) => report(name, &formatted, &error),
+        }
+    }
+
+    fn define_function(&mut self, name: &str, params: &[&str], formula: &str) -> String {
+        match self.store_function(name, params, formula) {
+            Ok(changed) => changed.iter().map(|changed| self.describe(changed)).collect(),
+            Err((formatted, error)) if formatted.is_empty() => format!("ERROR: {error}\n"),
+            Err((formatted, error)) => report(&format!("{name}({})", params.join(", ")), &formatted, &error),
+        }
+    }
+
+    // Stores the formula and recomputes it along with everything that depends on
+    // it. Returns the names recomputed, or the formula and why it was rejected
+    fn store(&mut self, name: &str, formula: &str) -> Result<Vec<String>, (String, CalcError)> {
+        let rejected = |message: String| Err((String::new(), CalcError::new(message)));
+        if functions::find_function(name).is_some() || self.settings.functions.contains_key(name) {
+            return rejected(format!("{name} is a function and cannot be redefined"));
+        }
+        if functions::find_constant(name).is_some() {
+            return rejected(format!("{name} is a constant and cannot be redefined"));
+        }
+        if name == "true" || name == "false" {
+            return rejected(format!("{name} is a truth value and cannot be redefined"));
+        }
+
+        let (formatted, expr) = parse_input(formula, &self.settings);
+        let expr = match expr {
+            Ok(expr) => expr,
+            Err(error) => return Err((formatted, error)),
+        };
+        if let Some(cycle) = self.cycle(name, &self.inputs(&expr)) {
+            return rejected(format!("Circular definition: {}", cycle.join(" -> ")));
+        }
+
+        let value = Err(CalcError::new("Not computed yet"));
+        self.definitions.insert(name.to_string(), Definition { formula: formatted, expr, value });
+
+        let changed: Vec<String> = std::iter::once(name.to_string()).chain(self.dependents(name)).collect();
+        for name in &changed {
+            self.recompute(name);
+        }
+        Ok(changed)
+    }
+
+    // Stores a function like store does a value, then recomputes the values that call it
+    fn store_function(&mut self, name: &str, params: &[&str], formula: &str) -> Result<Vec<String>, (String, CalcError)> {
+        let rejected = |message: String| Err((String::new(), CalcError::new(message)));
+        if functions::find_constant(name).is_some() {
+            return rejected(format!("{name} is a constant and cannot be redefined"));
+        }
+        if name == "true" || name == "false" {
+            return rejected(format!("{name} is a truth value and cannot be redefined"));
+        }
+        if self.definitions.contains_key(name) {
+            return rejected(format!("{name} is a value and cannot be redefined as a function"));
+        }
+        if let Some(param) = params.iter().enumerate().find_map(|(i, param)| params[..i].contains(param).then_some(param)) {
+            return rejected(format!("Parameter {param} appears more than once"));
+        }
+
+        let (formatted, body) = parse_input(formula, &self.settings);
+        let body = match body {
+            Ok(body) => body,
+            Err(error) => return Err((formatted, error)),
+        };
+        let function = UserFunction {
+            name: name.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+            body,
+            formula: formatted,
+        };
+        if let Some(cycle) = self.cycle(name, &self.function_inputs(&function)) {
+            return rejected(format!("Circular definition: {}", cycle.join(" -> ")));
+        }
+        self.settings.functions.insert(name.to_string(), function);
+
+        let changed: Vec<String> = std::iter::once(name.to_string()).chain(self.dependents(name)).collect();
+        for name in &changed {
+            self.recompute(name);
+        }
+        Ok(changed)
+    }
+
+    // Applies a definition or :set line of a script, None for expressions.
+    // Gives the text errors point into along with whether the line succeeded
+    pub fn apply_script_line(&mut self, line: &str) -> Option<(String, Result<(), CalcError>)> {
+        let line = line.trim();
+        if let Some(command) = line.strip_prefix(':') {
+            let result = match command.split_once(char::is_whitespace) {
+                Some(("set", setting)) => {
+                    let (name, value) = split_setting(setting.trim());
+                    self.change_setting(name, value)
+                },
+                _ => Err(CalcError::new(format!("Only :set applies in scripts, other commands are interactive: {line}"))),
+            };
+            return Some((line.to_string(), result));
+        }
+
+        if let Some((name, params, formula)) = split_function(line) {
+            return Some(match self.store_function(name, &params, formula) {
+                Ok(_) => (self.settings.functions[name].formula.clone(), Ok(())),
+                Err((formatted, error)) => (formatted, Err(error)),
+            });
+        }
+        let (name, formula) = split_definition(line)?;
+        Some(match self.store(name, formula) {
+            Ok(_) => {
+                let definition = &self.definitions[name];
+                (definition.formula.clone(), definition.value.clone().map(|_| ()))
+            },
+            Err((formatted, error)) => (formatted, Err(error)),
+        })
+    }
+
+    // Script that rebuilds the session: settings, definitions with each one after
+    // the names it uses, and the history as comments
+    pub fn script(&self) -> String {
+        let mut script = String::from("# Calculator session, replay with :load FILE or calculator -f FILE\n");
+
+        // Formulas are kept in plain syntax, read them back the same way
+        script.push_str(":set syntax infix\n:set locale c\n");
+        script.push_str(&format!(":set implicit {}\n", self.settings.implicit.name()));
+        script.push_str(&format!(":set angle {}\n", self.settings.angle.name()));
+
+        let mut ordered = Vec::<String>::new();
+        for name in self.settings.functions.keys().chain(self.definitions.keys()) {
+            self.inputs_first(name, &mut ordered);
+        }
+        for name in &ordered {
+            match self.settings.functions.get(name) {
+                Some(function) => script.push_str(&format!("{} = {}\n", function.signature(), function.formula)),
+                None => script.push_str(&format!("{name} = {}\n", self.definitions[name].formula)),
+            }
+        }
+
+        for (name, _) in SETTING_NAMES {
+            // Precision is a single setting shown two ways, only the one in use is written
+            let value = self.settings.get(name).unwrap_or_default();
+            if (*name == "sig" && value == "off") || (*name == "digits" && value == "off" && self.settings.get("sig").is_some_and(|sig| sig != "off")) {
+                continue;
+            }
+            script.push_str(&format!(":set {name} {value}\n"));
+        }
+
+        if !self.history.is_empty() {
+            script.push_str("# History\n");
+            for line in &self.history {
+                script.push_str(&format!("{HISTORY_PREFIX}{line}\n"));
+            }
+        }
+        script
+    }
+
+    fn inputs_first(&self, name: &str, ordered: &mut Vec<String>) {
+        let inputs = match (self.definitions.get(name), self.settings.functions.get(name)) {
+            (Some(definition), _) => self.inputs(&definition.expr),
+            (None, Some(function)) => self.function_inputs(function),
+            (None, None) => return,
+        };
+        if ordered.iter().any(|done| done == name) {
+            return;
+        }
+        for input in inputs {
+            self.inputs_first(&input, ordered);
+        }
+        ordered.push(name.to_string());
+    }
+
+    fn save(&self, file: &str) -> String {
+        match std::fs::write(file, self.script()) {
+            Ok(()) => format!("Saved {} definition(s) to {file}\n", self.definitions.len() + self.settings.functions.len()),
+            Err(error) => format!("ERROR: Could not write {file}: {error}\n"),
+        }
+    }
+
+    // Replays the file the way -f runs it, quietly apart from errors and the
+    // results of expressions, and takes back its history
+    fn load(&mut self, file: &str) -> String {
+        let text = match std::fs::read_to_string(file) {
+            Ok(text) => text,
+            Err(error) => return format!("ERROR: Could not read {file}: {error}\n"),
+        };
+
+        let mut output = String::new();
+        for (index, line) in text.lines().enumerate() {
+            let trimmed = line.trim();
+            if let Some(entry) = trimmed.strip_prefix(HISTORY_PREFIX) {
+                self.history.push(entry.to_string());
+                continue;
+            }
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            match self.apply_script_line(trimmed) {
+                Some((formatted, Err(error))) => {
+                    output.push_str(&format!("{file}:{}: ERROR: {error}\n{}", index + 1, error_pointer(&formatted, &error)));
+                },
+                Some((_, Ok(()))) => {},
+                None => output.push_str(&self.evaluate(trimmed)),
+            }
+        }
+
+        output.push_str(&format!("Loaded {file}\n"));
+        output
+    }
+
+    // Names a formula depends on: the names in it, and the functions it calls
+    // along with the names those depend on
+    fn inputs(&self, expr: &Expr) -> Vec<String> {
+        let mut inputs = Vec::<String>::new();
+        let mut add = |input: String| {
+            if !inputs.contains(&input) {
+                inputs.push(input);
+            }
+        };
+        expr.visit(&mut |expr| match expr {
+            Expr::Name(name) => add(name.clone()),
+            Expr::Call(name, _) if let Some(function) = self.settings.functions.get(name) => {
+                add(name.clone());
+                self.function_inputs(function).into_iter().for_each(&mut add);
+            },
+            _ => {},
+        });
+        inputs
+    }
+
+    // Inputs of the body other than the parameters
+    fn function_inputs(&self, function: &UserFunction) -> Vec<String> {
+        self.inputs(&function.body).into_iter()
+            .filter(|input| !function.params.contains(input))
+            .collect()
+    }
+
+    // Chain of names leading from the inputs back to name, if defining name with
+    // these inputs would make it depend on itself
+    fn cycle(&self, name: &str, inputs: &[String]) -> Option<Vec<String>> {
+        for input in inputs {
+            let mut path = vec![name.to_string()];
+            if self.path_to(input, name, &mut path) {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    fn path_to(&self, from: &str, to: &str, path: &mut Vec<String>) -> bool {
+        path.push(from.to_string());
+        if from == to {
+            return true;
+        }
+        if let Some(definition) = self.definitions.get(from) {
+            for input in self.inputs(&definition.expr) {
+                if self.path_to(&input, to, path) {
+                    return true;
+                }
+            }
+        }
+        path.pop();
+        false
+    }
+
+    // Every definition that depends on name, each after the ones it refers to
+    fn dependents(&self, name: &str) -> Vec<String> {
+        let mut finished = Vec::<String>::new();
+        self.visit_dependents(name, &mut finished);
+        // Reversed finishing order of the walk puts inputs first
+        finished.reverse();
+        finished
+    }
+
+    fn visit_dependents(&self, name: &str, finished: &mut Vec<String>) {
+        for (dependent, definition) in &self.definitions {
+            if self.inputs(&definition.expr).iter().any(|input| input == name) && !finished.contains(dependent) {
+                self.visit_dependents(dependent, finished);
+                finished.push(dependent.clone());
+            }
+        }
+    }
+
+    fn recompute(&mut self, name: &str) {
+        let Some(definition) = self.definitions.get(name) else {
+            return;
+        };
+        let failed = self.inputs(&definition.expr).into_iter()
+            .find(|input| self.definitions.get(input).is_some_and(|input| input.value.is_err()));
+        let value = match failed {
+            Some(input) => Err(CalcError::new(format!("Refers to {input}, which failed"))),
+            None => {
+                let expr = functions::expand(&definition.expr, &self.settings.functions);
+                eval::evaluate(&expr, &self.settings.scope, self.settings.angle)
+            },
+        };
+
+        match &value {
+            Ok(value) => self.settings.scope.insert(name.to_string(), value.clone()),
+            Err(_) => self.settings.scope.remove(name),
+        };
+        if let Some(definition) = self.definitions.get_mut(name) {
+            definition.value = value;
+        }
+    }
+
+    fn describe(&self, name: &str) -> String {
+        if self.settings.functions.contains_key(name) {
+            return format!("{}\n", self.summary(name));
+        }
+        let Some(definition) = self.definitions.get(name) else {
+            return String::new();
+        };
+        match &definition.value {
+            Ok(_) => format!("{}\n", self.summary(name)),
+            Err(error) => report(name, &definition.formula, error),
+        }
+    }
+
+    // One line about a name: its formula and value, or where its value comes from
+    fn summary(&self, name: &str) -> String {
+        if let Some(definition) = self.definitions.get(name) {
+            return match &definition.value {
+                Ok(value) => {
+                    let value = value.display(&self.settings.number);
+                    if value == definition.formula {
+                        format!("{name} = {value}")
+                    }
+                    else {
+                        format!("{name} = {} = {value}", definition.formula)
+                    }
+                },
+                Err(error) => format!("{name} = {} (failed: {error})", definition.formula),
+            };
+        }
+        if let Some(function) = self.settings.functions.get(name) {
+            return format!("{} = {}", function.signature(), function.formula);
+        }
+        if let Some(value) = self.settings.scope.get(name) {
+            return format!("{name} = {}", value.display(&self.settings.number));
+        }
+        match functions::find_constant(name) {
+            Some(value) => format!("{name} = {} (constant)", Value::Number(value).display(&self.settings.number)),
+            None => format!("{name} (undefined)"),
+        }
+    }
+
+    fn funcs(&self) -> String {
+        let mut output: String = functions::FUNCTIONS.iter()
+            .map(|function| format!("{:<18}{}\n", function.signature(), function.summary))
+            .collect();
+        for name in self.settings.functions.keys() {
+            output.push_str(&format!("{}\n", self.summary(name)));
+        }
+        output
+    }
+
+    // Tree of the values feeding name, each indented below the one it feeds
+    fn deps(&self, name: &str) -> String {
+        if !self.definitions.contains_key(name) {
+            return format!("ERROR: {name} is not defined\n");
+        }
+        let mut output = String::new();
+        self.feeds(name, 0, &mut output);
+        output
+    }
+
+    fn feeds(&self, name: &str, depth: usize, output: &mut String) {
+        output.push_str(&format!("{}{}\n", "  ".repeat(depth), self.summary(name)));
+        if let Some(definition) = self.definitions.get(name) {
+            for input in self.inputs(&definition.expr) {
+                self.feeds(&input, depth + 1, output);
+            }
+        }
+    }
+}
+
+fn help() -> String {
+    let mut text = format!("{INTRO}\nCommands:\n");
+    for (name, usage, about) in COMMANDS {
+        text.push_str(&format!("  {:<20}{about}\n", format!("{name} {usage}").trim_end()));
+    }
+    text.push_str(&format!("\nKeys:\n{KEYS}"));
+    text
+}
+
+
+// Name and formula of a line such as area = w * h
+pub(crate) fn split_definition(line: &str) -> Option<(&str, &str)> {
+    let (name, formula) = line.split_once('=')?;
+    let name = name.trim();
+    // == is a comparison, and <=, >= and != leave a symbol in the name
+    if formula.starts_with('=') || !is_identifier(name) {
+        return None;
+    }
+    Some((name, formula))
+}
+
+// Name, parameters and formula of a line such as f(x, y) = x^2 + y. Calls of
+// built in functions, such as max(a, b) = b, are comparisons instead
+pub(crate) fn split_function(line: &str) -> Option<(&str, Vec<&str>, &str)> {
+    let (head, formula) = line.split_once('=')?;
+    let (name, params) = head.trim().strip_suffix(')')?.split_once('(')?;
+    let name = name.trim();
+    let params: Vec<&str> = match params.trim() {
+        "" => Vec::new(),
+        params => params.split([',', ';']).map(str::trim).collect(),
+    };
+    if formula.starts_with('=') || !is_identifier(name) || functions::find_function(name).is_some() || !params.iter().all(|param| is_identifier(param)) {
+        return None;
+    }
+    Some((name, params, formula))
+}
+
+// Name and value of a setting written as NAME VALUE or NAME=VALUE
+fn split_setting(setting: &str) -> (&str, &str) {
+    setting.split_once([' ', '='])
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .unwrap_or((setting, ""))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// Error of a definition, pointing into its formula
+fn report(name: &str, formatted: &str, error: &CalcError) -> String {
+    let prefix = format!("{name} = ");
+    match error.column(formatted) {
+        Some(column) => format!("{prefix}{formatted}\n{}^\nERROR: {error}\n", " ".repeat(prefix.chars().count() + column)),
+        None => format!("{prefix}{formatted}\nERROR: {error}\n"),
+    }
+}
+
+// Reads definitions, commands and expressions until the end of input
+pub(crate) fn run(args: &Args, mut session: Session) {
+    let mut editor = Editor::new();
+
+    println!("{INTRO}Type :help for commands and keys, Ctrl-D quits.");
+    loop {
+        // Highlighting follows the infix lexer, so it only suits that syntax
+        editor.highlight = session.settings.syntax == Syntax::Infix;
+        let settings = &session.settings;
+        let complete = |line: &str, cursor: usize| completion::complete(line, cursor, &settings.scope, &settings.number);
+        let Some(line) = editor.read_line("> ", &complete) else {
+            break;
+        };
+
+        if let Some(output) = session.command(&line) {
+            print!("{output}");
+        }
+        else if !line.trim().is_empty() {
+            if args.explain {
+                print_explained(&line, &session.settings);
+            }
+            else if args.latex {
+                print_latex(&line, &session.settings);
+            }
+            else {
+                print!("{}", session.evaluate(&line));
+            }
+        }
+        if !line.trim().is_empty() {
+            session.history.push(line);
+        }
+    }
+    println!();
+}
@@ -0,0 +1,225 @@
+use std::io::{self, BufRead, IsTerminal};
+
+use crate::editor::Editor;
+use crate::error::CalcError;
+use crate::eval::{self, Scope, Value};
+use crate::functions;
+use crate::parse_tokens;
+use crate::parser::Expr;
+use crate::settings::{AngleUnit, NumberFormat, Settings};
+use crate::token::TokenType;
+
+const HELP: &str = "\
+Reverse Polish Notation: numbers and constants are pushed onto the stack,
+operators and functions take their operands from the top of it.
+  3 4 + 2 *        is (3 + 4) * 2
+  + - * / ^        and comparisons take two values
+  neg              negates the top value
+  sqrt, atan2 ...  take as many values as they have parameters
+  max:3, sum:5 ... take the count after : for functions of any number of values
+  swap             exchanges the top two values
+  dup              copies the top value
+  drop             removes the top value
+  roll             moves the bottom value to the top
+  clear            empties the stack
+";
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Stack {
+    // Bottom of the stack first, the top is the last value
+    pub values: Vec<Value>,
+    pub angle: AngleUnit,
+}
+
+impl Stack {
+    // Applies every word on the line in turn, on error the stack is left as it was
+    pub fn apply_line(&mut self, line: &str) -> Result<(), CalcError> {
+        let mut values = self.values.clone();
+        for word in line.split_whitespace() {
+            apply_word(&mut values, word, self.angle)?;
+        }
+        self.values = values;
+        Ok(())
+    }
+
+    // One line per level, the top of the stack last at level 1
+    pub fn render(&self, number: &NumberFormat) -> String {
+        if self.values.is_empty() {
+            return String::from("(empty)\n");
+        }
+
+        self.values.iter()
+            .enumerate()
+            .map(|(i, value)| format!("{}: {}\n", self.values.len() - i, value.display(number)))
+            .collect()
+    }
+}
+
+fn apply_word(values: &mut Vec<Value>, word: &str, angle: AngleUnit) -> Result<(), CalcError> {
+    if shuffle(values, word)? {
+        return Ok(());
+    }
+
+    // Operands are values already, so only the new node needs evaluating
+    let mut exprs: Vec<Expr> = values.iter().cloned().map(Value::into_expr).collect();
+    build(&mut exprs, word)?;
+    let Some(node) = exprs.pop() else {
+        return Ok(());
+    };
+    values.truncate(exprs.len());
+    values.push(eval::evaluate(&node, &Scope::new(), angle)?);
+    Ok(())
+}
+
+// Expression tree of a whole RPN expression such as 3 4 + 2 *
+pub(crate) fn parse_rpn(input: &str) -> Result<Expr, CalcError> {
+    let mut exprs = Vec::<Expr>::new();
+    for word in input.split_whitespace() {
+        if !shuffle(&mut exprs, word)? {
+            build(&mut exprs, word)?;
+        }
+    }
+
+    match exprs.len() {
+        1 => Ok(exprs.remove(0)),
+        0 => Err(CalcError::new("Empty expression")),
+        count => Err(CalcError::new(format!("Expression leaves {count} values on the stack, expected 1"))),
+    }
+}
+
+// Stack commands that only rearrange values, returns whether word was one
+fn shuffle<T: Clone>(values: &mut Vec<T>, word: &str) -> Result<bool, CalcError> {
+    match word {
+        "swap" => {
+            let [a, b] = pop(values, word)?;
+            values.extend([b, a]);
+        },
+        "dup" => {
+            let [a] = pop(values, word)?;
+            values.extend([a.clone(), a]);
+        },
+        "drop" => {
+            pop::<T, 1>(values, word)?;
+        },
+        "roll" => {
+            if !values.is_empty() {
+                let bottom = values.remove(0);
+                values.push(bottom);
+            }
+        },
+        "clear" => values.clear(),
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+// Replaces the operands of word on top of the stack with the node applying it
+fn build(exprs: &mut Vec<Expr>, word: &str) -> Result<(), CalcError> {
+    if word == "neg" {
+        let [a] = pop(exprs, word)?;
+        exprs.push(Expr::Negate(Box::new(a)));
+        return Ok(());
+    }
+
+    // Functions of any number of values name how many they take, 1 2 3 max:3
+    if let Some((name, count)) = word.split_once(':')
+        && let Some(function) = functions::find_function(name)
+        && function.variadic()
+    {
+        let count = count.parse::<usize>()
+            .ok()
+            .filter(|count| function.accepts(*count))
+            .ok_or_else(|| CalcError::new(format!("{} takes {} argument(s), not {count}", function.signature(), function.takes())))?;
+        let expr = call(exprs, function, count)?;
+        exprs.push(expr);
+        return Ok(());
+    }
+
+    let tokens = parse_tokens(word)?;
+    let [token] = tokens.as_slice() else {
+        return Err(CalcError::new(format!("Unknown word: {word}")));
+    };
+
+    let expr = match token.ttype {
+        TokenType::Number => Expr::Number(token.value.parse().map_err(|_| CalcError::new(format!("Could not parse number: {word}")))?),
+        TokenType::Text => Expr::Text(word[1..word.len() - 1].to_string()),
+        TokenType::Addition |
+        TokenType::Subtract |
+        TokenType::Multiply |
+        TokenType::Division |
+        TokenType::Exponent |
+        TokenType::Equal |
+        TokenType::NotEqual |
+        TokenType::Less |
+        TokenType::LessEqual |
+        TokenType::Greater |
+        TokenType::GreaterEqual |
+        TokenType::Concat => {
+            let [a, b] = pop(exprs, word)?;
+            Expr::Binary(token.ttype, Box::new(a), Box::new(b))
+        },
+        // Condition, then value, else value
+        TokenType::Question => {
+            let [condition, then, otherwise] = pop(exprs, word)?;
+            Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise))
+        },
+        TokenType::Identifier => match functions::find_function(word) {
+            // Without a count, functions of any number of values take the ones they name
+            Some(function) => call(exprs, function, function.arity())?,
+            None => Expr::Name(word.to_string()),
+        },
+        _ => return Err(CalcError::new(format!("Unknown word: {word}"))),
+    };
+
+    exprs.push(expr);
+    Ok(())
+}
+
+// Call of function on the top count values
+fn call(exprs: &mut Vec<Expr>, function: &functions::Function, count: usize) -> Result<Expr, CalcError> {
+    if exprs.len() < count {
+        return Err(CalcError::new(format!("{} needs {count} value(s) but the stack has {}", function.signature(), exprs.len())));
+    }
+    let args = exprs.split_off(exprs.len() - count);
+    Ok(Expr::Call(function.name.to_string(), args))
+}
+
+// Top N values, the topmost last
+fn pop<T, const N: usize>(values: &mut Vec<T>, word: &str) -> Result<[T; N], CalcError> {
+    if values.len() < N {
+        return Err(CalcError::new(format!("{word} needs {N} value(s) but the stack has {}", values.len())));
+    }
+    // split_off leaves exactly N values
+    let top = values.split_off(values.len() - N);
+    Ok(top.try_into().unwrap_or_else(|_| unreachable!()))
+}
+
+// Reads lines until the end of input, showing the stack after each one
+pub(crate) fn run_rpn(settings: &Settings) {
+    let mut stack = Stack { angle: settings.angle, ..Stack::default() };
+    let mut run = |line: &str| match stack.apply_line(line) {
+        Ok(()) => print!("{}", stack.render(&settings.number)),
+        Err(error) => eprintln!("ERROR: {error}"),
+    };
+
+    // Piped input is read as it is, without prompts
+    if !io::stdin().is_terminal() {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            run(&line);
+        }
+        return;
+    }
+
+    print!("{HELP}");
+    let mut editor = Editor::new();
+    // Words are not completed, function names would come with the parentheses of calls
+    let complete = |_: &str, cursor: usize| (cursor, Vec::new());
+    while let Some(line) = editor.read_line("> ", &complete) {
+        run(&line);
+    }
+    println!();
+}
@@ -0,0 +1,345 @@
+use crate::error::CalcError;
+use crate::eval::Scope;
+use crate::functions::Defined;
+use crate::locale::Locale;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) enum ImplicitMul {
+    // Juxtaposition binds like '*', so 6/2(1+2) = 9
+    #[default]
+    Standard,
+    // Juxtaposition binds tighter than '*' and '/', so 6/2(1+2) = 1
+    Tight,
+}
+
+impl ImplicitMul {
+    pub fn from_name(name: &str) -> Result<ImplicitMul, CalcError> {
+        match name {
+            "standard" => Ok(ImplicitMul::Standard),
+            "tight" => Ok(ImplicitMul::Tight),
+            _ => Err(CalcError::new(format!("Unknown implicit multiplication mode: {name} (expected standard or tight)"))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImplicitMul::Standard => "standard",
+            ImplicitMul::Tight => "tight",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) enum AngleUnit {
+    // Unit of the angles trigonometric functions take and give
+    #[default]
+    Radians,
+    Degrees,
+}
+
+impl AngleUnit {
+    pub fn from_name(name: &str) -> Result<AngleUnit, CalcError> {
+        match name {
+            "rad" => Ok(AngleUnit::Radians),
+            "deg" => Ok(AngleUnit::Degrees),
+            _ => Err(CalcError::new(format!("Unknown angle unit: {name} (expected rad or deg)"))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AngleUnit::Radians => "rad",
+            AngleUnit::Degrees => "deg",
+        }
+    }
+
+    // Angle in this unit as radians
+    pub fn to_radians(self, angle: f64) -> f64 {
+        match self {
+            AngleUnit::Radians => angle,
+            AngleUnit::Degrees => angle.to_radians(),
+        }
+    }
+
+    // Angle in radians as this unit
+    pub fn of_radians(self, radians: f64) -> f64 {
+        match self {
+            AngleUnit::Radians => radians,
+            AngleUnit::Degrees => radians.to_degrees(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) enum OutputFormat {
+    // Result only, errors on stderr
+    #[default]
+    Plain,
+    // One JSON object per expression
+    Json,
+    Csv,
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Result<OutputFormat, CalcError> {
+        match name {
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(CalcError::new(format!("Unknown output format: {name} (expected plain, json, csv or tsv)"))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Plain => "plain",
+            OutputFormat::Json => "json",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Tsv => "tsv",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) enum Notation {
+    // Plain positional digits
+    #[default]
+    Auto,
+    // One digit before the point, 1.2345e3
+    Scientific,
+    // Exponent a multiple of three, 12.345e3
+    Engineering,
+}
+
+impl Notation {
+    pub fn from_name(name: &str) -> Result<Notation, CalcError> {
+        match name {
+            "auto" => Ok(Notation::Auto),
+            "sci" => Ok(Notation::Scientific),
+            "eng" => Ok(Notation::Engineering),
+            _ => Err(CalcError::new(format!("Unknown notation: {name} (expected auto, sci or eng)"))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Notation::Auto => "auto",
+            Notation::Scientific => "sci",
+            Notation::Engineering => "eng",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) enum Precision {
+    // Fewest digits that read back as the same value
+    #[default]
+    Shortest,
+    // Fixed number of digits after the point
+    Decimals(usize),
+    // Fixed number of significant figures
+    Significant(usize),
+}
+
+// Most digits a result is shown with, the longest exact expansion of an f64
+// has 767 significant digits
+pub(crate) const MAX_DIGITS: usize = 767;
+
+impl Precision {
+    pub fn decimals(digits: usize) -> Result<Precision, CalcError> {
+        if digits > MAX_DIGITS {
+            return Err(CalcError::new(format!("Digits after the point must be at most {MAX_DIGITS}")));
+        }
+        Ok(Precision::Decimals(digits))
+    }
+
+    pub fn significant(figures: usize) -> Result<Precision, CalcError> {
+        if figures == 0 {
+            return Err(CalcError::new("Significant figures must be at least 1"));
+        }
+        if figures > MAX_DIGITS {
+            return Err(CalcError::new(format!("Significant figures must be at most {MAX_DIGITS}")));
+        }
+        Ok(Precision::Significant(figures))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) struct NumberFormat {
+    pub notation: Notation,
+    pub precision: Precision,
+    // Decimal and digit group separators, for input as well as results
+    pub locale: Locale,
+}
+
+impl NumberFormat {
+    // Comma or space separated items such as "sci, sig=3" or "digits=2, locale=de"
+    pub fn from_spec(spec: &str) -> Result<NumberFormat, CalcError> {
+        let mut format = NumberFormat::default();
+
+        for item in spec.split([',', ' ']).filter(|item| !item.is_empty()) {
+            match item.split_once('=') {
+                None => format.notation = match item {
+                    "auto" => Notation::Auto,
+                    "sci" => Notation::Scientific,
+                    "eng" => Notation::Engineering,
+                    _ => return Err(CalcError::new(format!("Unknown number format: {item} (expected auto, sci, eng, digits=N or sig=N)"))),
+                },
+                Some((name, count)) => {
+                    if name == "locale" {
+                        format.locale = Locale::from_name(count)?;
+                        continue;
+                    }
+                    let count: usize = count.parse()
+                        .map_err(|_| CalcError::new(format!("Expected a whole number in {item}")))?;
+                    format.precision = match name {
+                        "digits" => Precision::decimals(count)?,
+                        "sig" => Precision::significant(count)?,
+                        _ => return Err(CalcError::new(format!("Unknown number format: {item} (expected digits=N, sig=N or locale=NAME)"))),
+                    };
+                },
+            }
+        }
+
+        Ok(format)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub(crate) enum Syntax {
+    // Plain expressions such as 2(3 + 4)
+    #[default]
+    Infix,
+    // LaTeX math such as \frac{1}{2} + \sqrt{3}
+    Latex,
+    // Reverse Polish Notation such as 3 4 + 2 *
+    Rpn,
+    // S-expressions such as (* (+ 3 4) 2)
+    Sexpr,
+    // Spreadsheet formulas such as =SUM(A1:A3)*2
+    Sheet,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Result<Syntax, CalcError> {
+        match name {
+            "infix" => Ok(Syntax::Infix),
+            "latex" => Ok(Syntax::Latex),
+            "rpn" => Ok(Syntax::Rpn),
+            "sexpr" => Ok(Syntax::Sexpr),
+            "sheet" => Ok(Syntax::Sheet),
+            _ => Err(CalcError::new(format!("Unknown syntax: {name} (expected infix, latex, rpn, sexpr or sheet)"))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Syntax::Infix => "infix",
+            Syntax::Latex => "latex",
+            Syntax::Rpn => "rpn",
+            Syntax::Sexpr => "sexpr",
+            Syntax::Sheet => "sheet",
+        }
+    }
+}
+
+// How expressions are written back out by fmt
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Style {
+    // Spaces around binary operators and after commas
+    pub spaces: bool,
+    // Multiplication sign, '*' or '×'
+    pub times: char,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style { spaces: true, times: '*' }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Settings {
+    pub syntax: Syntax,
+    // Values of names such as spreadsheet cells
+    pub scope: Scope,
+    // Functions defined in the session
+    pub functions: Defined,
+    pub implicit: ImplicitMul,
+    pub angle: AngleUnit,
+    pub format: OutputFormat,
+    pub number: NumberFormat,
+}
+
+// Settings that can be changed by name, with the values they take
+pub(crate) const SETTING_NAMES: &[(&str, &str)] = &[
+    ("notation", "auto, sci or eng"),
+    ("digits",   "N, or off for as many as needed"),
+    ("sig",      "N, or off for as many as needed"),
+    ("locale",   "c, en, ch, de or fr"),
+    ("syntax",   "infix, latex, rpn, sexpr or sheet"),
+    ("implicit", "standard or tight"),
+    ("angle",    "rad or deg"),
+    ("format",   "plain, json, csv or tsv"),
+];
+
+impl Settings {
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), CalcError> {
+        let precision = || -> Result<Option<usize>, CalcError> {
+            if value == "off" {
+                return Ok(None);
+            }
+            value.parse::<usize>()
+                .map(Some)
+                .map_err(|_| CalcError::new(format!("{name} requires a whole number or off, found {value}")))
+        };
+
+        match name {
+            "notation" => self.number.notation = Notation::from_name(value)?,
+            "digits" => self.number.precision = match precision()? {
+                Some(digits) => Precision::decimals(digits)?,
+                None => Precision::Shortest,
+            },
+            "sig" => self.number.precision = match precision()? {
+                Some(figures) => Precision::significant(figures)?,
+                None => Precision::Shortest,
+            },
+            "locale" => self.number.locale = Locale::from_name(value)?,
+            "syntax" => self.syntax = Syntax::from_name(value)?,
+            "implicit" => self.implicit = ImplicitMul::from_name(value)?,
+            "angle" => self.angle = AngleUnit::from_name(value)?,
+            "format" => self.format = OutputFormat::from_name(value)?,
+            _ => return Err(CalcError::new(format!("Unknown setting: {name} (expected {})", setting_list()))),
+        }
+        Ok(())
+    }
+
+    // Current value of a setting as set accepts it
+    pub fn get(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "notation" => self.number.notation.name().to_string(),
+            "digits" => match self.number.precision {
+                Precision::Decimals(digits) => digits.to_string(),
+                _ => String::from("off"),
+            },
+            "sig" => match self.number.precision {
+                Precision::Significant(figures) => figures.to_string(),
+                _ => String::from("off"),
+            },
+            "locale" => self.number.locale.name().to_string(),
+            "syntax" => self.syntax.name().to_string(),
+            "implicit" => self.implicit.name().to_string(),
+            "angle" => self.angle.name().to_string(),
+            "format" => self.format.name().to_string(),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+fn setting_list() -> String {
+    let names: Vec<&str> = SETTING_NAMES.iter().map(|(name, _)| *name).collect();
+    names.join(", ")
+}
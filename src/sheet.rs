@@ -0,0 +1,644 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use crate::error::CalcError;
+use crate::eval::{self, Scope, Value};
+use crate::functions;
+use crate::output;
+use crate::parser::Expr;
+use crate::settings::{AngleUnit, NumberFormat};
+use crate::token::TokenType;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Number(f64),
+    Text(String),
+    // Cell reference such as A1, without any $ markers
+    Cell(String),
+    // Cells from the first to the second reference, A1:B3
+    Range(String, String),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+    Comma,
+}
+
+// Expression tree of a spreadsheet formula such as =SUM(A1:A3)*2, where cells
+// are names looked up in the grid
+pub(crate) fn parse_formula(input: &str) -> Result<Expr, CalcError> {
+    let input = input.trim();
+    let input = input.strip_prefix('=').unwrap_or(input);
+
+    let parts = split_parts(input)?;
+    let mut parser = Parser { parts: &parts, pos: 0 };
+    let expr = parser.comparison()?;
+
+    match parser.peek() {
+        Some(Part::Close) => Err(CalcError::new("Unmatched ')'")),
+        Some(part) => Err(CalcError::new(format!("Unexpected token: {}", describe(part)))),
+        None => Ok(expr),
+    }
+}
+
+fn split_parts(input: &str) -> Result<Vec<Part>, CalcError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut parts = Vec::<Part>::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let cur = chars[i];
+        i += 1;
+
+        match cur {
+            c if c.is_whitespace() => {},
+            '0'..='9' | '.' => {
+                let start = i - 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let number: String = chars[start..i].iter().collect();
+                let number = number.parse::<f64>()
+                    .map_err(|_| CalcError::new(format!("Could not parse number: {number}")))?;
+                parts.push(Part::Number(number));
+            },
+            // Text in double quotes, "" inside it is a quote
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match (chars.get(i), chars.get(i + 1)) {
+                        (Some('"'), Some('"')) => {
+                            text.push('"');
+                            i += 2;
+                        },
+                        (Some('"'), _) => {
+                            i += 1;
+                            break;
+                        },
+                        (Some(&c), _) => {
+                            text.push(c);
+                            i += 1;
+                        },
+                        (None, _) => return Err(CalcError::new("Unterminated text, expected a closing '\"'")),
+                    }
+                }
+                parts.push(Part::Text(text));
+            },
+            c if c.is_alphabetic() || c == '$' || c == '_' => {
+                let start = i - 1;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '$' || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                // A name followed by ( is a function, so LOG10(100) is not cell LOG10
+                let is_call = chars[i..].iter().find(|c| !c.is_whitespace()) == Some(&'(');
+                parts.push(match cell_name(&word) {
+                    Some(cell) if !is_call => Part::Cell(cell),
+                    _ => Part::Name(word.to_lowercase()),
+                });
+            },
+            '(' => parts.push(Part::Open),
+            ')' => parts.push(Part::Close),
+            ',' | ';' => parts.push(Part::Comma),
+            ':' => {
+                let (Some(Part::Cell(from)), Some(Part::Cell(to))) = (parts.pop(), next_cell(&chars, &mut i)) else {
+                    return Err(CalcError::new("A range must join two cells, such as A1:B3"));
+                };
+                parts.push(Part::Range(from, to));
+            },
+            '<' | '>' if chars.get(i) == Some(&'=') => {
+                parts.push(Part::Op(if cur == '<' { "<=" } else { ">=" }));
+                i += 1;
+            },
+            '<' if chars.get(i) == Some(&'>') => {
+                parts.push(Part::Op("<>"));
+                i += 1;
+            },
+            '+' => parts.push(Part::Op("+")),
+            '-' => parts.push(Part::Op("-")),
+            '*' => parts.push(Part::Op("*")),
+            '/' => parts.push(Part::Op("/")),
+            '^' => parts.push(Part::Op("^")),
+            '&' => parts.push(Part::Op("&")),
+            '%' => parts.push(Part::Op("%")),
+            '=' => parts.push(Part::Op("=")),
+            '<' => parts.push(Part::Op("<")),
+            '>' => parts.push(Part::Op(">")),
+            _ => return Err(CalcError::new(format!("Unknown operator: {cur}"))),
+        }
+    }
+
+    Ok(parts)
+}
+
+// Cell reference following a ':' in a range
+fn next_cell(chars: &[char], i: &mut usize) -> Option<Part> {
+    while chars.get(*i).is_some_and(|c| c.is_whitespace()) {
+        *i += 1;
+    }
+    let start = *i;
+    while chars.get(*i).is_some_and(|c| c.is_alphanumeric() || *c == '$') {
+        *i += 1;
+    }
+    let word: String = chars[start..*i].iter().collect();
+    cell_name(&word).map(Part::Cell)
+}
+
+// Upper case cell name such as B12 for b12 or $B$12, None for other words
+fn cell_name(word: &str) -> Option<String> {
+    let name: String = word.chars().filter(|c| *c != '$').collect::<String>().to_uppercase();
+    cell_position(&name).map(|_| name)
+}
+
+// Zero based column and row of a cell name such as B12
+pub(crate) fn cell_position(name: &str) -> Option<(usize, usize)> {
+    let letters = name.find(|c: char| !c.is_ascii_uppercase())?;
+    let (column, row) = name.split_at(letters);
+    if column.is_empty() || column.len() > 3 || row.starts_with('0') || !row.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let column = column.bytes().fold(0, |index, letter| index * 26 + (letter - b'A') as usize + 1) - 1;
+    let row = row.parse::<usize>().ok()? - 1;
+    Some((column, row))
+}
+
+// Cell name of a zero based column and row, 0 and 0 is A1
+pub(crate) fn cell_at(column: usize, row: usize) -> String {
+    let mut letters = Vec::<char>::new();
+    let mut index = column + 1;
+    while index > 0 {
+        letters.push((b'A' + ((index - 1) % 26) as u8) as char);
+        index = (index - 1) / 26;
+    }
+    let column: String = letters.iter().rev().collect();
+    format!("{column}{}", row + 1)
+}
+
+// Cells a range may cover, as each one becomes an argument
+const MAX_RANGE_CELLS: usize = 100_000;
+
+// Every cell of a range, row by row
+fn expand_range(from: &str, to: &str) -> Result<Vec<Expr>, CalcError> {
+    let (Some((col_a, row_a)), Some((col_b, row_b))) = (cell_position(from), cell_position(to)) else {
+        return Ok(Vec::new());
+    };
+    let count = (col_a.abs_diff(col_b) + 1).saturating_mul(row_a.abs_diff(row_b) + 1);
+    if count > MAX_RANGE_CELLS {
+        return Err(CalcError::new(format!("The range {from}:{to} covers {count} cells, at most {MAX_RANGE_CELLS} are supported")));
+    }
+
+    let mut cells = Vec::<Expr>::new();
+    for row in row_a.min(row_b)..=row_a.max(row_b) {
+        for column in col_a.min(col_b)..=col_a.max(col_b) {
+            cells.push(Expr::Name(cell_at(column, row)));
+        }
+    }
+    Ok(cells)
+}
+
+fn describe(part: &Part) -> String {
+    match part {
+        Part::Number(number) => number.to_string(),
+        Part::Text(text) => format!("\"{text}\""),
+        Part::Cell(cell) => cell.clone(),
+        Part::Range(from, to) => format!("{from}:{to}"),
+        Part::Name(name) => name.clone(),
+        Part::Op(op) => op.to_string(),
+        Part::Open => String::from("("),
+        Part::Close => String::from(")"),
+        Part::Comma => String::from(","),
+    }
+}
+
+struct Parser<'a> {
+    parts: &'a [Part],
+    pos: usize,
+}
+
+// Spreadsheet precedence from loosest to tightest: comparisons, &, + and -,
+// * and /, ^ (left to right), %, then unary minus, so -2^2 is 4
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Part> {
+        self.parts.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&'a Part, CalcError> {
+        let part = self.parts.get(self.pos)
+            .ok_or_else(|| CalcError::new("Unexpected end of formula"))?;
+        self.pos += 1;
+        Ok(part)
+    }
+
+    // Operator from ops at the current position, consumed when found
+    fn operator(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Part::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(op)
+            },
+            _ => None,
+        }
+    }
+
+    // One level of left associative binary operators
+    fn binary(&mut self, ops: &[&'static str], operand: fn(&mut Self) -> Result<Expr, CalcError>) -> Result<Expr, CalcError> {
+        let mut lhs = operand(self)?;
+        while let Some(op) = self.operator(ops) {
+            let rhs = operand(self)?;
+            let ttype = match op {
+                "=" => TokenType::Equal,
+                "<>" => TokenType::NotEqual,
+                "<" => TokenType::Less,
+                "<=" => TokenType::LessEqual,
+                ">" => TokenType::Greater,
+                ">=" => TokenType::GreaterEqual,
+                "&" => TokenType::Concat,
+                "+" => TokenType::Addition,
+                "-" => TokenType::Subtract,
+                "*" => TokenType::Multiply,
+                "/" => TokenType::Division,
+                _ => TokenType::Exponent,
+            };
+            lhs = Expr::Binary(ttype, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, CalcError> {
+        self.binary(&["=", "<>", "<", "<=", ">", ">="], Self::concat)
+    }
+
+    fn concat(&mut self) -> Result<Expr, CalcError> {
+        self.binary(&["&"], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, CalcError> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, CalcError> {
+        self.binary(&["*", "/"], Self::power)
+    }
+
+    fn power(&mut self) -> Result<Expr, CalcError> {
+        self.binary(&["^"], Self::percent)
+    }
+
+    fn percent(&mut self) -> Result<Expr, CalcError> {
+        let mut expr = self.unary()?;
+        while self.operator(&["%"]).is_some() {
+            expr = Expr::Binary(TokenType::Division, Box::new(expr), Box::new(Expr::Number(100.)));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CalcError> {
+        match self.operator(&["-", "+"]) {
+            Some("-") => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(_) => self.unary(),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CalcError> {
+        match self.next()? {
+            Part::Number(number) => Ok(Expr::Number(*number)),
+            Part::Text(text) => Ok(Expr::Text(text.clone())),
+            Part::Cell(cell) => Ok(Expr::Name(cell.clone())),
+            Part::Range(from, to) => Err(CalcError::new(format!("The range {from}:{to} can only be a function argument"))),
+            Part::Open => {
+                let inner = self.comparison()?;
+                match self.next() {
+                    Ok(Part::Close) => Ok(inner),
+                    _ => Err(CalcError::new("'(' is never closed, expected ')'")),
+                }
+            },
+            Part::Name(name) if self.peek() == Some(&Part::Open) => {
+                self.pos += 1;
+                let args = self.arguments()?;
+                call(name, args)
+            },
+            Part::Name(name) if name == "true" || name == "false" => Ok(Expr::Bool(name == "true")),
+            Part::Name(name) => Ok(Expr::Name(name.clone())),
+            part => Err(CalcError::new(format!("Unexpected token: {}", describe(part)))),
+        }
+    }
+
+    // Comma separated arguments after the opening paren, ranges become their cells
+    fn arguments(&mut self) -> Result<Vec<Vec<Expr>>, CalcError> {
+        let mut args = Vec::<Vec<Expr>>::new();
+        if self.peek() == Some(&Part::Close) {
+            self.pos += 1;
+            return Ok(args);
+        }
+
+        loop {
+            match self.peek() {
+                Some(Part::Range(from, to)) if matches!(self.parts.get(self.pos + 1), Some(Part::Comma | Part::Close)) => {
+                    self.pos += 1;
+                    args.push(expand_range(from, to)?);
+                },
+                _ => args.push(vec![self.comparison()?]),
+            }
+            match self.next() {
+                Ok(Part::Comma) => continue,
+                Ok(Part::Close) => return Ok(args),
+                _ => return Err(CalcError::new("'(' is never closed, expected ')'")),
+            }
+        }
+    }
+}
+
+// Spreadsheet functions written with the nodes the evaluator already has
+fn call(name: &str, args: Vec<Vec<Expr>>) -> Result<Expr, CalcError> {
+    let count = args.len();
+    let arity = |expected: &str| CalcError::new(format!("{} takes {expected} argument(s) but {count} were given", name.to_uppercase()));
+
+    match name {
+        "if" => {
+            let [condition, then, otherwise] = single_args(args).try_into().map_err(|_| arity("3"))?;
+            // Any number but 0 counts as true, comparisons already give a truth value
+            let condition = match condition {
+                Expr::Bool(_) => condition,
+                Expr::Binary(ttype, ..) if is_comparison(ttype) => condition,
+                _ => Expr::Call(String::from("bool"), vec![condition]),
+            };
+            Ok(Expr::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+        },
+        "round" => match single_args(args).as_slice() {
+            [x] => Ok(Expr::Call(String::from("round"), vec![x.clone()])),
+            // round(x * 10^n) / 10^n
+            [x, digits] => {
+                let scale = Expr::Binary(TokenType::Exponent, Box::new(Expr::Number(10.)), Box::new(digits.clone()));
+                let scaled = Expr::Binary(TokenType::Multiply, Box::new(x.clone()), Box::new(scale.clone()));
+                Ok(Expr::Binary(TokenType::Division, Box::new(Expr::Call(String::from("round"), vec![scaled])), Box::new(scale)))
+            },
+            _ => Err(arity("1 or 2")),
+        },
+        "power" => {
+            let [base, exponent] = single_args(args).try_into().map_err(|_| arity("2"))?;
+            Ok(Expr::Binary(TokenType::Exponent, Box::new(base), Box::new(exponent)))
+        },
+        // One call over every value, however many cells the ranges cover
+        "sum" | "average" | "min" | "max" | "concat" | "concatenate" => {
+            let name = if name == "concatenate" { "concat" } else { name };
+            let mut values = args.concat();
+            match values.len() {
+                0 if name == "sum" => Ok(Expr::Number(0.)),
+                0 => Err(arity("at least 1")),
+                1 if name == "concat" => Ok(Expr::Binary(TokenType::Concat, Box::new(values.remove(0)), Box::new(Expr::Text(String::new())))),
+                1 => Ok(values.remove(0)),
+                _ => Ok(Expr::Call(name.to_string(), values)),
+            }
+        },
+        "log10" => call("log", args),
+        "pi" if count == 0 => Ok(Expr::Name(String::from("pi"))),
+        "true" | "false" if count == 0 => Ok(Expr::Bool(name == "true")),
+        _ => {
+            let function = functions::find_function(name)
+                .ok_or_else(|| CalcError::new(format!("Unknown function: {}", name.to_uppercase())))?;
+            let args = single_args(args);
+            if !function.accepts(args.len()) {
+                return Err(arity(&function.takes()));
+            }
+            Ok(Expr::Call(function.name.to_string(), args))
+        },
+    }
+}
+
+fn is_comparison(ttype: TokenType) -> bool {
+    matches!(ttype, TokenType::Equal | TokenType::NotEqual | TokenType::Less | TokenType::LessEqual | TokenType::Greater | TokenType::GreaterEqual)
+}
+
+// Arguments where a range counts as one argument per cell
+fn single_args(args: Vec<Vec<Expr>>) -> Vec<Expr> {
+    args.concat()
+}
+
+// Rows of comma separated values, fields may be quoted with "" for a quote
+pub(crate) fn read_csv(text: &str) -> Result<Vec<Vec<String>>, CalcError> {
+    let mut rows = Vec::<Vec<String>>::new();
+    let mut row = Vec::<String>::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            },
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => row.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes => {},
+            '\n' if !in_quotes => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(CalcError::new("Unterminated quoted field, expected a closing '\"'"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+// Cells of a CSV file by name
+pub(crate) fn read_grid(text: &str) -> Result<Scope, CalcError> {
+    let mut grid = Scope::new();
+    for (row, fields) in read_csv(text)?.into_iter().enumerate() {
+        for (column, field) in fields.into_iter().enumerate() {
+            grid.insert(cell_at(column, row), plain_value(&field));
+        }
+    }
+
+    Ok(grid)
+}
+
+// The grid with the cells the expression refers to beyond it added as empty
+// ones, which read as 0
+pub(crate) fn with_empty_cells<'a>(expr: &Expr, grid: &'a Scope) -> Cow<'a, Scope> {
+    let missing: Vec<String> = references(expr).into_iter()
+        .filter(|cell| !grid.contains_key(cell))
+        .collect();
+    if missing.is_empty() {
+        return Cow::Borrowed(grid);
+    }
+
+    let mut grid = grid.clone();
+    for cell in missing {
+        grid.insert(cell, Value::Number(0.));
+    }
+    Cow::Owned(grid)
+}
+
+// Number where the field parses as one and text otherwise, empty fields count as 0
+fn plain_value(field: &str) -> Value {
+    let trimmed = field.trim();
+    match trimmed.parse::<f64>() {
+        Ok(number) => Value::Number(number),
+        Err(_) if trimmed.is_empty() => Value::Number(0.),
+        Err(_) => Value::Text(field.to_string()),
+    }
+}
+
+fn is_formula(field: &str) -> bool {
+    field.trim_start().starts_with('=')
+}
+
+// Evaluates the formulas of a CSV file and prints every cell as CSV, "-" reads
+// stdin. Failing cells print as #ERROR and are reported. Returns whether all succeeded
+pub(crate) fn run_sheet(file: &str, number: &NumberFormat) -> bool {
+    let source = if file == "-" { "<stdin>" } else { file };
+    let mut text = String::new();
+    let read = if file == "-" {
+        io::stdin().read_to_string(&mut text).map(|_| ())
+    }
+    else {
+        std::fs::read_to_string(file).map(|read| text = read)
+    };
+    if let Err(error) = read {
+        eprintln!("ERROR: Could not read {source}: {error}");
+        return false;
+    }
+
+    let rows = match read_csv(&text) {
+        Ok(rows) => rows,
+        Err(error) => {
+            eprintln!("{source}: ERROR: {error}");
+            return false;
+        },
+    };
+
+    let (values, errors) = evaluate_sheet(&rows, number);
+    for row in values {
+        println!("{}", row.iter().map(|field| output::csv_field(field)).collect::<Vec<String>>().join(","));
+    }
+    for (cell, error) in &errors {
+        eprintln!("{source}:{cell}: ERROR: {error}");
+    }
+
+    errors.is_empty()
+}
+
+// Every cell as written out, formulas replaced by their values, along with the
+// failing cells in row order
+pub(crate) fn evaluate_sheet(rows: &[Vec<String>], number: &NumberFormat) -> (Vec<Vec<String>>, Vec<(String, CalcError)>) {
+    let mut engine = Engine::default();
+    for (row, fields) in rows.iter().enumerate() {
+        for (column, field) in fields.iter().enumerate() {
+            let name = cell_at(column, row);
+            if is_formula(field) {
+                engine.formulas.insert(name, parse_formula(field));
+            }
+            else {
+                engine.values.insert(name, plain_value(field));
+            }
+        }
+    }
+
+    let mut values = Vec::<Vec<String>>::new();
+    let mut errors = Vec::<(String, CalcError)>::new();
+    for (row, fields) in rows.iter().enumerate() {
+        let mut output = Vec::<String>::new();
+        for (column, field) in fields.iter().enumerate() {
+            if !is_formula(field) {
+                output.push(field.clone());
+                continue;
+            }
+
+            let name = cell_at(column, row);
+            engine.resolve(&name);
+            match (engine.values.get(&name), engine.failed.get(&name)) {
+                (Some(value), _) => output.push(value.display(number)),
+                (None, error) => {
+                    output.push(String::from("#ERROR"));
+                    let error = error.cloned().unwrap_or_else(|| CalcError::new("Cell was never evaluated"));
+                    errors.push((name, error));
+                },
+            }
+        }
+        values.push(output);
+    }
+
+    (values, errors)
+}
+
+#[derive(Default)]
+struct Engine {
+    formulas: HashMap<String, Result<Expr, CalcError>>,
+    // Plain cells and the formulas evaluated so far
+    values: Scope,
+    failed: HashMap<String, CalcError>,
+    // Formulas being resolved, each one referring to the next
+    path: Vec<String>,
+}
+
+impl Engine {
+    // Evaluates a formula cell after the cells it refers to
+    fn resolve(&mut self, name: &str) {
+        if self.values.contains_key(name) || self.failed.contains_key(name) {
+            return;
+        }
+        let expr = match self.formulas.get(name) {
+            Some(Ok(expr)) => expr.clone(),
+            Some(Err(error)) => {
+                self.failed.insert(name.to_string(), error.clone());
+                return;
+            },
+            // Cells outside the file are empty
+            None => {
+                self.values.insert(name.to_string(), Value::Number(0.));
+                return;
+            },
+        };
+
+        // Back at a cell still being resolved, every cell since then is in the loop
+        if let Some(start) = self.path.iter().position(|cell| cell == name) {
+            let cycle = format!("{} -> {name}", self.path[start..].join(" -> "));
+            for cell in &self.path[start..] {
+                self.failed.insert(cell.clone(), CalcError::new(format!("Circular reference: {cycle}")));
+            }
+            return;
+        }
+
+        let references = references(&expr);
+        self.path.push(name.to_string());
+        for reference in &references {
+            self.resolve(reference);
+        }
+        self.path.pop();
+
+        if self.failed.contains_key(name) {
+            return;
+        }
+        if let Some(reference) = references.iter().find(|reference| self.failed.contains_key(*reference)) {
+            self.failed.insert(name.to_string(), CalcError::new(format!("Refers to {reference}, which failed")));
+            return;
+        }
+        match eval::evaluate(&expr, &self.values, AngleUnit::Radians) {
+            Ok(value) => {
+                self.values.insert(name.to_string(), value);
+            },
+            Err(error) => {
+                self.failed.insert(name.to_string(), error);
+            },
+        }
+    }
+}
+
+// Cell names an expression refers to, in order of appearance
+fn references(expr: &Expr) -> Vec<String> {
+    expr.names().into_iter()
+        .filter(|name| cell_position(name).is_some())
+        .collect()
+}
@@ -0,0 +1,130 @@
+// Raw mode for the terminal on stdin through the C library's termios calls, so
+// keys arrive one at a time without being echoed
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::ffi::c_int;
+
+    #[cfg(target_os = "linux")]
+    type Flag = u32;
+    #[cfg(target_os = "macos")]
+    type Flag = std::ffi::c_ulong;
+
+    #[cfg(target_os = "linux")]
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: Flag,
+        pub c_oflag: Flag,
+        pub c_cflag: Flag,
+        pub c_lflag: Flag,
+        c_line: u8,
+        pub c_cc: [u8; 32],
+        c_ispeed: u32,
+        c_ospeed: u32,
+    }
+
+    #[cfg(target_os = "macos")]
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct Termios {
+        pub c_iflag: Flag,
+        pub c_oflag: Flag,
+        pub c_cflag: Flag,
+        pub c_lflag: Flag,
+        pub c_cc: [u8; 20],
+        c_ispeed: Flag,
+        c_ospeed: Flag,
+    }
+
+    #[cfg(target_os = "linux")]
+    mod flags {
+        use super::Flag;
+        pub const ICRNL: Flag = 0o400;
+        pub const IXON: Flag = 0o2000;
+        pub const ISIG: Flag = 0o1;
+        pub const ICANON: Flag = 0o2;
+        pub const ECHO: Flag = 0o10;
+        pub const IEXTEN: Flag = 0o100000;
+        pub const VTIME: usize = 5;
+        pub const VMIN: usize = 6;
+    }
+
+    #[cfg(target_os = "macos")]
+    mod flags {
+        use super::Flag;
+        pub const ICRNL: Flag = 0x100;
+        pub const IXON: Flag = 0x200;
+        pub const ISIG: Flag = 0x80;
+        pub const ICANON: Flag = 0x100;
+        pub const ECHO: Flag = 0x8;
+        pub const IEXTEN: Flag = 0x400;
+        pub const VMIN: usize = 16;
+        pub const VTIME: usize = 17;
+    }
+
+    pub use flags::*;
+
+    // Apply once output written so far has been sent, dropping unread input
+    pub const TCSAFLUSH: c_int = 2;
+    pub const STDIN: c_int = 0;
+
+    unsafe extern "C" {
+        pub fn tcgetattr(fd: c_int, termios: *mut Termios) -> c_int;
+        pub fn tcsetattr(fd: c_int, actions: c_int, termios: *const Termios) -> c_int;
+    }
+}
+
+// Restores the terminal settings it replaced when dropped
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub(crate) struct RawMode {
+    original: sys::Termios,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl RawMode {
+    // None when stdin is not a terminal
+    pub fn enable() -> Option<RawMode> {
+        let mut original = std::mem::MaybeUninit::<sys::Termios>::uninit();
+        // SAFETY: tcgetattr fills in the whole struct when it succeeds
+        let original = unsafe {
+            if sys::tcgetattr(sys::STDIN, original.as_mut_ptr()) != 0 {
+                return None;
+            }
+            original.assume_init()
+        };
+
+        let mut raw = original;
+        raw.c_iflag &= !(sys::ICRNL | sys::IXON);
+        raw.c_lflag &= !(sys::ECHO | sys::ICANON | sys::ISIG | sys::IEXTEN);
+        // Reads wait for a single byte
+        raw.c_cc[sys::VMIN] = 1;
+        raw.c_cc[sys::VTIME] = 0;
+        // SAFETY: raw is a valid termios copied from the current settings
+        if unsafe { sys::tcsetattr(sys::STDIN, sys::TCSAFLUSH, &raw) } != 0 {
+            return None;
+        }
+
+        Some(RawMode { original })
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: original holds the settings read by tcgetattr
+        unsafe {
+            sys::tcsetattr(sys::STDIN, sys::TCSAFLUSH, &self.original);
+        }
+    }
+}
+
+// Other systems read whole lines without editing
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) struct RawMode;
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+impl RawMode {
+    pub fn enable() -> Option<RawMode> {
+        None
+    }
+}
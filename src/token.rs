@@ -8,18 +8,46 @@ pub enum TokenType {
     Exponent,
     ParenOpen,
     ParenClose,
+    BracketOpen,
+    BracketClose,
+    BraceOpen,
+    BraceClose,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Concat,
+    Question,
+    Colon,
+    If,
+    Then,
+    Else,
+    Identifier,
+    Comma,
+    Root,
+    Text,
+    Bar,
+    FloorOpen,
+    FloorClose,
+    CeilOpen,
+    CeilClose,
 }
 
 #[allow(non_snake_case)]
 pub mod TokenPrio {
     pub const NONE: i8  = 0;
-    pub const ADD: i8   = 1;
-    pub const SUB: i8   = 1;
-    pub const MUL: i8   = 2;
-    pub const DIV: i8   = 2;
-    pub const EXP: i8   = 3;
-    pub const PAR: i8   = 4;
-    pub const MAX: i8   = 4;
+    pub const COND: i8  = 1;
+    pub const CMP: i8   = 2;
+    pub const CAT: i8   = 3;
+    pub const ADD: i8   = 4;
+    pub const SUB: i8   = 4;
+    pub const MUL: i8   = 5;
+    pub const DIV: i8   = 5;
+    pub const IMP: i8   = 6;
+    pub const EXP: i8   = 7;
+    pub const PAR: i8   = 8;
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -28,3 +56,24 @@ pub(crate) struct Token {
     pub ttype: TokenType,
     pub prio: i8,
 }
+
+// Splits a formatted expression on the spaces between tokens, keeping quoted text whole
+pub(crate) fn split_formatted(formatted: &str) -> Vec<&str> {
+    let mut parts = Vec::<&str>::new();
+    let mut start = 0;
+    let mut in_quotes = false;
+
+    for (i, c) in formatted.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ' ' if !in_quotes => {
+                parts.push(&formatted[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    parts.push(&formatted[start..]);
+
+    parts
+}